@@ -0,0 +1,278 @@
+//! Aggregates over the numeric values of a path and groups of documents sharing a key.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use indexmap::map::IndexMap;
+use crate::{FloatKey, Index, IndexOrd, MultiMap, path, planner};
+
+/// An aggregate over the numeric values found at a dot path, other values are ignored. the elements of an array are
+/// aggregated one by one, but a value repeated within one document counts once: `{"n": [5, 5, 7]}` adds 5 and 7,
+/// like the trees, which hold each key of a document once
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Agg {
+    /// number of numeric values
+    Count,
+    /// an integer while every value is an integer and the sum fits in an `i64`, a float otherwise
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// Folds numeric values into every aggregate at once
+#[derive(Clone, Debug)]
+pub(crate) struct Accumulator {
+    count: usize,
+    int_sum: Option<i64>,
+    float_sum: f64,
+    floats: bool,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+impl Accumulator {
+    pub(crate) fn new() -> Self {
+        Accumulator { count: 0, int_sum: Some(0), float_sum: 0.0, floats: false, min: None, max: None }
+    }
+
+    /// Adds a value `times` times, values that are not numbers are ignored
+    pub(crate) fn add(&mut self, v: &Value, times: usize) {
+        if times == 0 || !v.is_number() {
+            return;
+        }
+        match v.as_i64() {
+            Some(i) => {
+                self.int_sum = self.int_sum.and_then(|sum| i.checked_mul(times as i64).and_then(|i| sum.checked_add(i)));
+            }
+            None => self.floats = true
+        }
+        self.float_sum += v.as_f64().unwrap() * times as f64;
+        self.count += times;
+        let (below, above) = match (&self.min, &self.max) {
+            (Some(min), Some(max)) => (compare_numbers(v, min) == Ordering::Less, compare_numbers(v, max) == Ordering::Greater),
+            _ => (true, true)
+        };
+        if below {
+            self.min = Some(v.clone())
+        }
+        if above {
+            self.max = Some(v.clone())
+        }
+    }
+
+    /// Returns the aggregate, `None` for every aggregate but `Count` when no value was added
+    pub(crate) fn get(&self, agg: Agg) -> Option<Value> {
+        if self.count == 0 {
+            return if agg == Agg::Count { Some(Value::from(0)) } else { None };
+        }
+        match agg {
+            Agg::Count => Some(Value::from(self.count)),
+            Agg::Sum => match self.int_sum {
+                Some(sum) if !self.floats => Some(Value::from(sum)),
+                _ => Some(Value::from(self.float_sum))
+            },
+            Agg::Avg => Some(Value::from(self.float_sum / self.count as f64)),
+            Agg::Min => self.min.clone(),
+            Agg::Max => self.max.clone(),
+        }
+    }
+}
+
+/// Orders two numbers, integers are compared exactly
+pub(crate) fn compare_numbers(lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs.as_i64(), rhs.as_i64()) {
+        (Some(l), Some(r)) => l.cmp(&r),
+        _ => lhs.as_f64().partial_cmp(&rhs.as_f64()).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Aggregates the distinct values at `field` of each document
+pub(crate) fn documents<'v>(field: &str, docs: impl Iterator<Item=&'v Value>, agg: Agg) -> Option<Value> {
+    let mut acc = Accumulator::new();
+    docs.for_each(|doc| {
+        let values = planner::field_values(field, doc);
+        values.iter().enumerate()
+            .filter(|(i, v)| !values[..*i].contains(v))
+            .for_each(|(_, v)| acc.add(v, 1))
+    });
+    acc.get(agg)
+}
+
+/// Aggregates a path of an index. when the trees hold every document having the path, the aggregate is read from
+/// the trees: min and max from their first and last keys, the others from the keys and the sizes of their buckets.
+/// otherwise the documents the index accepts are scanned
+pub(crate) fn index(index: &Index, field: &str, agg: Agg) -> Option<Value> {
+    let field = path::canonical(field);
+    if !planner::covers(index, &field) {
+        let items = index.items.read().unwrap();
+        return documents(&field, items.values().filter(|v| index.accepts(v)), agg);
+    }
+    let int_tree = index.int_tree.read().unwrap();
+    let float_tree = index.float_tree.read().unwrap();
+    let ints = int_tree.get(&field);
+    let floats = float_tree.get(&field);
+    let mut acc = Accumulator::new();
+    match agg {
+        Agg::Min => {
+            if let Some(k) = ints.and_then(|t| t.keys().next()) {
+                acc.add(&Value::from(*k), 1)
+            }
+            if let Some(k) = floats.and_then(|t| t.keys().next()) {
+                acc.add(&Value::from(k.0), 1)
+            }
+        }
+        Agg::Max => {
+            if let Some(k) = ints.and_then(|t| t.keys().next_back()) {
+                acc.add(&Value::from(*k), 1)
+            }
+            if let Some(k) = floats.and_then(|t| t.keys().next_back()) {
+                acc.add(&Value::from(k.0), 1)
+            }
+        }
+        _ => {
+            ints.into_iter().flatten().for_each(|(k, bucket)| acc.add(&Value::from(*k), bucket.len()));
+            floats.into_iter().flatten().for_each(|(k, bucket)| acc.add(&Value::from(k.0), bucket.len()));
+        }
+    }
+    acc.get(agg)
+}
+
+/// The documents sharing one key of a path, returned by `GroupBy::get`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    pub key: Value,
+    /// documents holding the key
+    pub count: usize,
+    /// one value per `GroupBy::aggregate`, in the order they were added
+    pub aggregates: Vec<Option<Value>>,
+}
+
+#[derive(Clone, Copy)]
+enum GroupOrder {
+    Key,
+    Count,
+    Aggregate(usize),
+}
+
+/// Groups the documents of an index by the keys of a path, created by `Index::group_by`. the groups are the buckets
+/// of the trees of the path, documents holding several keys at the path are in several groups
+pub struct GroupBy<'i> {
+    index: &'i Index,
+    field: String,
+    aggregates: Vec<(String, Agg)>,
+    order: GroupOrder,
+    ord: IndexOrd,
+}
+
+impl<'i> GroupBy<'i> {
+    pub(crate) fn new(index: &'i Index, field: &str) -> Self {
+        GroupBy {
+            index,
+            field: path::canonical(field),
+            aggregates: vec![],
+            order: GroupOrder::Key,
+            ord: IndexOrd::ASC,
+        }
+    }
+
+    /// Adds an aggregate of another path computed over the documents of each group
+    pub fn aggregate(&mut self, path: &str, agg: Agg) -> &mut Self {
+        self.aggregates.push((path.to_string(), agg));
+        self
+    }
+
+    /// Orders the groups by key, the default. numbers come before strings
+    pub fn order_by_key(&mut self, ord: IndexOrd) -> &mut Self {
+        self.order = GroupOrder::Key;
+        self.ord = ord;
+        self
+    }
+
+    /// Orders the groups by document count, groups with the same count are ordered by key
+    pub fn order_by_count(&mut self, ord: IndexOrd) -> &mut Self {
+        self.order = GroupOrder::Count;
+        self.ord = ord;
+        self
+    }
+
+    /// Orders the groups by the aggregate added at position `i`, groups without a value come first in ascending
+    /// order and ties are ordered by key
+    pub fn order_by_aggregate(&mut self, i: usize, ord: IndexOrd) -> &mut Self {
+        self.order = GroupOrder::Aggregate(i);
+        self.ord = ord;
+        self
+    }
+
+    pub fn get(&self) -> Vec<Group> {
+        let items = self.index.items.read().unwrap();
+        let mut groups: Vec<Group> = with_buckets(self.index, &items, &self.field, |buckets| buckets.iter().map(|(key, docs)| {
+            let aggregates = self.aggregates.iter().map(|(path, agg)| {
+                documents(path, docs.iter().filter_map(|k| items.get(k)), *agg)
+            }).collect();
+            Group { key: key.clone(), count: docs.len(), aggregates }
+        }).collect());
+        let compare = |a: &Group, b: &Group| match self.order {
+            GroupOrder::Key => Ordering::Equal,
+            GroupOrder::Count => a.count.cmp(&b.count),
+            GroupOrder::Aggregate(i) => compare_aggregates(a.aggregates.get(i), b.aggregates.get(i)),
+        };
+        match self.ord {
+            IndexOrd::ASC => groups.sort_by(compare),
+            IndexOrd::DESC => groups.sort_by(|a, b| compare(b, a)),
+        }
+        if let (GroupOrder::Key, IndexOrd::DESC) = (self.order, self.ord) {
+            groups.reverse()
+        }
+        groups
+    }
+}
+
+fn compare_aggregates(lhs: Option<&Option<Value>>, rhs: Option<&Option<Value>>) -> Ordering {
+    match (lhs.cloned().flatten(), rhs.cloned().flatten()) {
+        (Some(l), Some(r)) => compare_numbers(&l, &r),
+        (l, r) => l.is_some().cmp(&r.is_some())
+    }
+}
+
+/// Calls `f` with the keys of a path and the documents holding them, numbers ordered before strings. the buckets of
+/// the trees are lent under their read locks when they hold every document having the path, otherwise they are built
+/// from the documents the index accepts
+pub(crate) fn with_buckets<R>(index: &Index, items: &IndexMap<String, Value>, field: &str, f: impl FnOnce(&[(Value, &HashSet<String>)]) -> R) -> R {
+    if planner::covers(index, field) {
+        let int_tree = index.int_tree.read().unwrap();
+        let float_tree = index.float_tree.read().unwrap();
+        let str_tree = index.str_tree.read().unwrap();
+        return f(&ordered_buckets(int_tree.get(field), float_tree.get(field), str_tree.get(field)));
+    }
+    with_document_buckets(items.iter().filter(|(_, v)| index.accepts(v)), field, f)
+}
+
+/// Calls `f` with the keys of a path and the documents holding them, read from the documents
+pub(crate) fn with_document_buckets<'d, R>(docs: impl Iterator<Item=(&'d String, &'d Value)>, field: &str, f: impl FnOnce(&[(Value, &HashSet<String>)]) -> R) -> R {
+    let mut ints: MultiMap<i64, String> = BTreeMap::new();
+    let mut floats: MultiMap<FloatKey, String> = BTreeMap::new();
+    let mut strs: MultiMap<String, String> = BTreeMap::new();
+    docs.for_each(|(k, v)| {
+        planner::field_values(field, v).into_iter().for_each(|value| {
+            if let Some(i) = value.as_i64() {
+                ints.entry(i).or_default().insert(k.to_string());
+            } else if let Some(f) = value.as_f64() {
+                floats.entry(FloatKey(f)).or_default().insert(k.to_string());
+            } else if let Value::String(s) = value {
+                strs.entry(s).or_default().insert(k.to_string());
+            }
+        })
+    });
+    f(&ordered_buckets(Some(&ints), Some(&floats), Some(&strs)))
+}
+
+fn ordered_buckets<'t>(ints: Option<&'t MultiMap<i64, String>>, floats: Option<&'t MultiMap<FloatKey, String>>, strs: Option<&'t MultiMap<String, String>>) -> Vec<(Value, &'t HashSet<String>)> {
+    let mut out: Vec<(Value, &HashSet<String>)> = vec![];
+    ints.into_iter().flatten().for_each(|(k, bucket)| out.push((Value::from(*k), bucket)));
+    floats.into_iter().flatten().for_each(|(k, bucket)| out.push((Value::from(k.0), bucket)));
+    out.sort_by(|(a, _), (b, _)| compare_numbers(a, b));
+    strs.into_iter().flatten().for_each(|(k, bucket)| out.push((Value::from(k.as_str()), bucket)));
+    out
+}
@@ -0,0 +1,180 @@
+//! A document set stored once with any number of named indexes over it.
+
+use indexmap::map::IndexMap;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use crate::{BatchTransaction, Index, Indexer, Plan, Query, QueryResult};
+use crate::planner::Planner;
+
+/// Stores every document once and keeps a set of named [`Index`]es over them consistent on insert, update, remove
+/// and batch. each index only holds its trees, documents its indexer does not accept are stored but not indexed
+/// ## Example
+/// ```rust
+/// use indexer::{Collection, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op};
+/// use serde_json::json;
+/// let mut users = Collection::new();
+/// users.add_index("age", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+/// users.add_index("email", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("email", IndexOrd::ASC)] }));
+/// users.insert("user.1", json!({"name": "Kwadwo", "age": 21, "email": "kwadwo@example.com"}));
+/// users.insert("user.2", json!({"name": "Kwame", "age": 35}));
+/// assert_eq!(users.index("age").unwrap().find_where("age", Op::GT, 18).count(), 2);
+/// assert_eq!(users.index("email").unwrap().find_where("email", Op::LIKE, "*@example.com").count(), 1);
+/// ```
+#[derive(Default)]
+pub struct Collection {
+    items: Arc<RwLock<IndexMap<String, Value>>>,
+    /// the keys of `items` in order, shared by the indexes for their key scans
+    keys: Arc<RwLock<BTreeSet<String>>>,
+    indexes: IndexMap<String, Index>,
+}
+
+impl Collection {
+    pub fn new() -> Self {
+        Collection::default()
+    }
+
+    /// Adds an index built over the documents already stored, an index with the same name is replaced
+    pub fn add_index(&mut self, name: &str, indexer: Indexer) {
+        let index = Index::shared(indexer, self.items.clone(), self.keys.clone());
+        self.indexes.insert(name.to_string(), index);
+    }
+
+    /// Drops an index, returns false when there is none with that name
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        self.indexes.shift_remove(name).is_some()
+    }
+
+    /// Returns an index to query. its documents are the ones of the collection, so `size` counts every document of
+    /// the collection, including those the index does not accept. it refuses changes, even through a clone, so
+    /// documents are changed through the collection only
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        self.indexes.get(name)
+    }
+
+    /// Names of the indexes in the order they were added
+    pub fn index_names(&self) -> Vec<&str> {
+        self.indexes.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Indexes of the collection by name
+    pub fn indexes(&self) -> impl Iterator<Item=(&str, &Index)> {
+        self.indexes.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Inserts a new document or overrides a previous one
+    pub fn insert<V>(&mut self, key: &str, value: V) where V: Serialize {
+        let v = serde_json::to_value(value).unwrap();
+        let mut items = self.items.write().unwrap();
+        let previous = items.insert(key.to_string(), v.clone());
+        if previous.is_none() {
+            self.keys.write().unwrap().insert(key.to_string());
+        }
+        self.indexes.values().for_each(|index| index.reindex(key, previous.as_ref(), Some(&v)));
+    }
+
+    /// Replaces a document, returns false and does nothing when there is no document with that key
+    pub fn update<V>(&mut self, key: &str, value: V) -> bool where V: Serialize {
+        if !self.contains_key(key) {
+            return false;
+        }
+        self.insert(key, value);
+        true
+    }
+
+    /// Removes a document from the collection and every index, returns it if there was one
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let mut items = self.items.write().unwrap();
+        let previous = items.shift_remove(key)?;
+        self.keys.write().unwrap().remove(key);
+        self.indexes.values().for_each(|index| index.reindex(key, Some(&previous), None));
+        Some(previous)
+    }
+
+    /// Answers a boolean query from the indexes that fit it best, documents are only scanned for conditions no index
+    /// answers. see [`Query`]
+    pub fn find(&self, q: &Query) -> QueryResult {
+        self.planner().find(q)
+    }
+
+    /// Returns how `find` answers a query
+    pub fn plan(&self, q: &Query) -> Plan {
+        self.planner().plan(q)
+    }
+
+    fn planner(&self) -> Planner<'_> {
+        Planner { items: &self.items, indexes: self.indexes().collect() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.items.read().unwrap().get(key).cloned()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.items.read().unwrap().contains_key(key)
+    }
+
+    /// Number of documents
+    pub fn size(&self) -> usize {
+        self.items.read().unwrap().len()
+    }
+
+    /// Batch transaction on the collection, the changes are applied to the documents and every index by ```b.commit()```
+    pub fn batch(&mut self, f: impl Fn(&mut CollectionBatch)) {
+        let mut batch = CollectionBatch {
+            collection: self,
+            inserts: HashMap::new(),
+            updates: HashMap::new(),
+            deletes: HashSet::new(),
+        };
+        f(&mut batch);
+    }
+}
+
+pub struct CollectionBatch<'a> {
+    collection: &'a mut Collection,
+    inserts: HashMap<String, Value>,
+    updates: HashMap<String, Value>,
+    deletes: HashSet<String>,
+}
+
+impl<'a> BatchTransaction<'a> for CollectionBatch<'a> {
+    fn insert<V>(&mut self, k: &str, v: V) where V: Serialize + Deserialize<'a> {
+        self.inserts.insert(k.to_string(), serde_json::to_value(v).unwrap());
+    }
+
+    fn update<V>(&mut self, k: &str, v: V) where V: Serialize + Deserialize<'a> {
+        self.updates.insert(k.to_string(), serde_json::to_value(v).unwrap());
+    }
+
+    fn delete(&mut self, k: &str) {
+        self.deletes.insert(k.to_string());
+    }
+
+    /// Applies inserts, then updates of the documents that exist, then deletes, under one write lock
+    fn commit(&mut self) {
+        let mut items = self.collection.items.write().unwrap();
+        let mut keys = self.collection.keys.write().unwrap();
+        let indexes = &self.collection.indexes;
+        self.inserts.drain().for_each(|(k, v)| {
+            let previous = items.insert(k.to_string(), v.clone());
+            if previous.is_none() {
+                keys.insert(k.to_string());
+            }
+            indexes.values().for_each(|index| index.reindex(&k, previous.as_ref(), Some(&v)));
+        });
+        self.updates.drain().for_each(|(k, v)| {
+            if let Some(current) = items.get_mut(&k) {
+                let previous = std::mem::replace(current, v.clone());
+                indexes.values().for_each(|index| index.reindex(&k, Some(&previous), Some(&v)));
+            }
+        });
+        self.deletes.drain().for_each(|k| {
+            if let Some(previous) = items.shift_remove(&k) {
+                keys.remove(&k);
+                indexes.values().for_each(|index| index.reindex(&k, Some(&previous), None));
+            }
+        });
+    }
+}
@@ -0,0 +1,93 @@
+//! Distinct keys and facet counts of paths.
+
+use indexmap::map::IndexMap;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Document counts of a facet by label, see [`Facet`]
+pub type FacetCounts = IndexMap<String, usize>;
+
+/// A facet of a path. values are counted once per document, array elements one by one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Facet {
+    /// counts of every key of the path ordered by key, or the `top` most frequent ordered by count
+    Values { path: String, top: Option<usize> },
+    /// counts of the numeric values of the path between consecutive bounds, labelled `..b0`, `b0..b1`, ..., `bn..`.
+    /// each range includes its lower bound
+    Ranges { path: String, bounds: Vec<f64> },
+}
+
+impl Facet {
+    pub fn values(path: &str) -> Self {
+        Facet::Values { path: path.to_string(), top: None }
+    }
+
+    pub fn top(path: &str, n: usize) -> Self {
+        Facet::Values { path: path.to_string(), top: Some(n) }
+    }
+
+    /// Ranges between bounds, the bounds are sorted
+    pub fn ranges(path: &str, bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Facet::Ranges { path: path.to_string(), bounds }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Facet::Values { path, .. } | Facet::Ranges { path, .. } => path
+        }
+    }
+}
+
+/// Counts a facet from the buckets of its path, see `aggregate::with_buckets`. only the documents in `keys` are
+/// counted when it is given
+pub(crate) fn counts(facet: &Facet, buckets: &[(Value, &HashSet<String>)], keys: Option<&HashSet<&str>>) -> FacetCounts {
+    let selected = |k: &&String| keys.is_none_or(|keys| keys.contains(k.as_str()));
+    match facet {
+        Facet::Values { top, .. } => {
+            let mut counts: Vec<(String, usize)> = buckets.iter()
+                .map(|(key, bucket)| (label(key), bucket.iter().filter(selected).count()))
+                .filter(|(_, count)| *count > 0)
+                .collect();
+            if let Some(n) = top {
+                counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                counts.truncate(*n);
+            }
+            counts.into_iter().collect()
+        }
+        Facet::Ranges { bounds, .. } => {
+            let mut ranges: Vec<HashSet<&str>> = vec![HashSet::new(); bounds.len() + 1];
+            buckets.iter().for_each(|(key, bucket)| {
+                if let Some(f) = key.as_f64() {
+                    let range = bounds.partition_point(|b| *b <= f);
+                    ranges[range].extend(bucket.iter().filter(selected).map(|k| k.as_str()));
+                }
+            });
+            ranges.into_iter().enumerate().map(|(i, docs)| {
+                let lower = if i == 0 { String::new() } else { bounds[i - 1].to_string() };
+                let upper = bounds.get(i).map(|b| b.to_string()).unwrap_or_default();
+                (format!("{}..{}", lower, upper), docs.len())
+            }).collect()
+        }
+    }
+}
+
+/// Distinct keys of a path in the order of `aggregate::with_buckets`, restricted to the documents in `keys` when given
+pub(crate) fn distinct(buckets: &[(Value, &HashSet<String>)], keys: Option<&HashSet<&str>>) -> Vec<Value> {
+    buckets.iter()
+        .filter(|(_, bucket)| match keys {
+            Some(keys) => bucket.iter().any(|k| keys.contains(k.as_str())),
+            None => !bucket.is_empty()
+        })
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn label(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.to_string(),
+        key => key.to_string()
+    }
+}
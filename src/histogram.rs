@@ -0,0 +1,97 @@
+//! Histograms and percentiles of numeric paths, computed from the sizes of the buckets in key order.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::{Index, aggregate, path};
+
+/// How `Index::histogram` splits the values of a path
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Histogram {
+    /// `n` ranges of the same width between the smallest and the largest value
+    EquiWidth(usize),
+    /// up to `n` ranges holding about the same number of values. the values of one key are never split, so a
+    /// frequent key makes its range deeper and the histogram may have fewer ranges
+    EquiDepth(usize),
+}
+
+/// A range of a histogram. equi-width ranges include their lower bound, the last one its upper bound too.
+/// equi-depth ranges go from their smallest to their largest value, both included
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// Numeric keys of a path in order with the number of documents holding each
+fn numeric_keys(index: &Index, field: &str) -> Vec<(Value, usize)> {
+    let items = index.items.read().unwrap();
+    aggregate::with_buckets(index, &items, &path::canonical(field), |buckets| buckets.iter()
+        .filter(|(key, _)| key.is_number())
+        .map(|(key, bucket)| (key.clone(), bucket.len()))
+        .collect())
+}
+
+pub(crate) fn histogram(index: &Index, field: &str, histogram: Histogram) -> Vec<HistogramBucket> {
+    let keys: Vec<(f64, usize)> = numeric_keys(index, field).into_iter().map(|(k, n)| (k.as_f64().unwrap(), n)).collect();
+    let (min, max) = match (keys.first(), keys.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => return vec![]
+    };
+    match histogram {
+        Histogram::EquiWidth(0) | Histogram::EquiDepth(0) => vec![],
+        Histogram::EquiWidth(n) => {
+            let n = if min == max { 1 } else { n };
+            let width = (max - min) / n as f64;
+            let mut out: Vec<HistogramBucket> = (0..n).map(|i| HistogramBucket {
+                lower: min + width * i as f64,
+                upper: if i + 1 == n { max } else { min + width * (i + 1) as f64 },
+                count: 0,
+            }).collect();
+            keys.iter().for_each(|(k, count)| {
+                let i = (((k - min) / width) as usize).min(n - 1);
+                out[i].count += count;
+            });
+            out
+        }
+        Histogram::EquiDepth(n) => {
+            let total: usize = keys.iter().map(|(_, count)| count).sum();
+            let mut out: Vec<HistogramBucket> = vec![];
+            let mut seen = 0;
+            // a range is closed once it reaches the next multiple of total / n, a key going past several of them
+            // closes one range only
+            let mut next = 0;
+            keys.iter().for_each(|(k, count)| {
+                match out.last_mut() {
+                    Some(last) if n * seen < next * total => {
+                        last.upper = *k;
+                        last.count += count;
+                    }
+                    _ => {
+                        out.push(HistogramBucket { lower: *k, upper: *k, count: *count });
+                        next = n * seen / total + 1;
+                    }
+                }
+                seen += count;
+            });
+            out
+        }
+    }
+}
+
+/// Nearest-rank percentiles, `None` for a `p` outside 0..=1 or when the path has no numeric value
+pub(crate) fn percentiles(index: &Index, field: &str, ps: &[f64]) -> Vec<Option<Value>> {
+    let keys = numeric_keys(index, field);
+    let total: usize = keys.iter().map(|(_, count)| count).sum();
+    ps.iter().map(|p| {
+        if total == 0 || !(0.0..=1.0).contains(p) {
+            return None;
+        }
+        let rank = ((p * total as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        keys.iter().find(|(_, count)| {
+            seen += count;
+            seen >= rank
+        }).map(|(key, _)| key.clone())
+    }).collect()
+}
@@ -0,0 +1,66 @@
+//! Joins of query results with the documents of another index.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use crate::{Index, Op, path, planner};
+
+/// How `QueryResult::join` combines a document with the documents it references
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Join {
+    /// one row per referenced document, documents referencing none are left out
+    Inner,
+    /// like `Inner`, documents referencing none get one row without a right side
+    Left,
+    /// one row without a right side per document referencing at least one document
+    Semi,
+}
+
+/// A row of a join: a document of the query result and one document it references
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JoinRow {
+    pub key: String,
+    pub left: Value,
+    /// key and document of the referenced document
+    pub right: Option<(String, Value)>,
+}
+
+/// Joins documents to the documents of `right` whose value at `right_field` equals one of their values at `field`.
+/// the values are looked up in the trees of `right` when they hold the path, otherwise its documents are read once
+pub(crate) fn join(left: &[(String, Value)], field: &str, right: &Index, right_field: &str, kind: Join) -> Vec<JoinRow> {
+    let right_field = path::canonical(right_field);
+    let items = right.items.read().unwrap();
+    // without a tree for the path, the documents of `right` are read once and grouped by their values
+    let lookup: Option<HashMap<String, Vec<&String>>> = match planner::covers(right, &right_field) {
+        true => None,
+        false => {
+            let mut lookup: HashMap<String, Vec<&String>> = HashMap::new();
+            items.iter().filter(|(_, v)| right.accepts(v)).for_each(|(k, v)| {
+                planner::field_values(&right_field, v).iter().for_each(|value| lookup.entry(value.to_string()).or_default().push(k))
+            });
+            Some(lookup)
+        }
+    };
+    let mut rows = vec![];
+    left.iter().for_each(|(key, doc)| {
+        let mut matches = BTreeSet::new();
+        planner::field_values(field, doc).iter().for_each(|value| match &lookup {
+            Some(lookup) => matches.extend(lookup.get(&value.to_string()).into_iter().flatten().map(|k| k.to_string())),
+            None => right.scan(&right_field, value, &Op::EQ, false, None, &mut |_, bucket| {
+                matches.extend(bucket.iter().filter(|k| items.contains_key(*k)).cloned());
+                true
+            })
+        });
+        let row = |right: Option<(String, Value)>| JoinRow { key: key.to_string(), left: doc.clone(), right };
+        match kind {
+            Join::Semi if !matches.is_empty() => rows.push(row(None)),
+            Join::Semi => {}
+            Join::Left if matches.is_empty() => rows.push(row(None)),
+            Join::Inner | Join::Left => matches.into_iter().for_each(|k| {
+                let doc = items.get(&k).cloned().unwrap_or(Value::Null);
+                rows.push(row(Some((k, doc))))
+            }),
+        }
+    });
+    rows
+}
@@ -15,6 +15,9 @@ extern crate serde;
 extern crate serde_json;
 extern crate rayon;
 extern crate glob;
+extern crate ciborium;
+extern crate crc32fast;
+extern crate memmap2;
 
 use ordered_float::OrderedFloat;
 use indexmap::map::IndexMap;
@@ -23,13 +26,51 @@ use json_dotpath::DotPaths;
 use std::cmp::Ordering;
 use rayon::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::{HashSet, HashMap, BTreeMap};
-use std::sync::{RwLock, Arc};
+use serde::de::DeserializeOwned;
+use std::collections::{HashSet, HashMap, BTreeMap, BTreeSet};
+use std::sync::{RwLock, Arc, Mutex};
 use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 use std::fmt;
 use std::error;
-use std::ops::Bound::{Included, Unbounded};
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+use std::ops::RangeBounds;
+use std::sync::RwLockReadGuard;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use path::PathExpr;
+use snapshot::Snapshot;
+use wal::{Wal, WalEntry};
+
+pub use wal::FsyncPolicy;
+pub use mmap::MmapIndex;
+pub use collection::{Collection, CollectionBatch};
+pub use planner::{Query, Plan};
+pub use stats::{IndexStats, PathStats, BucketSizes};
+pub use aggregate::{Agg, Group, GroupBy};
+pub use facet::{Facet, FacetCounts};
+pub use histogram::{Histogram, HistogramBucket};
+pub use sketch::{Sketch, HyperLogLog, TDigest};
+pub use join::{Join, JoinRow};
+pub use projection::Projection;
+pub use typed::{TypedIndex, DocumentError, DocumentErrorKind};
+
+mod aggregate;
+mod collection;
+mod facet;
+mod histogram;
+mod join;
+mod mmap;
+mod path;
+mod planner;
+mod projection;
+mod sketch;
+mod snapshot;
+mod stats;
+mod typed;
+mod wal;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum Indexer {
@@ -39,6 +80,7 @@ pub enum Indexer {
     String(IndexString),
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Op {
     EQ,
     LT,
@@ -57,6 +99,77 @@ impl fmt::Display for UnknownOperatorError {
 
 impl error::Error for UnknownOperatorError {}
 
+/// Errors of loading a snapshot or replaying a write-ahead log. records are numbered from 0 in the order they were written
+#[derive(Debug)]
+pub enum PersistError {
+    Io(io::Error),
+    /// the input is not a snapshot or log written by this crate
+    NotAnIndex,
+    UnsupportedVersion(u8),
+    /// the input ends inside a record
+    Truncated { record: usize },
+    /// a record does not match its checksum
+    Corrupt { record: usize },
+    /// a record matches its checksum but is not a valid entry
+    Decode { record: usize, message: String },
+    /// the records do not match the count or checksum at the end of the snapshot
+    ChecksumMismatch,
+}
+
+impl PersistError {
+    /// True when the error comes from damaged data rather than from reading it
+    pub fn is_damage(&self) -> bool {
+        matches!(self, PersistError::Truncated { .. } | PersistError::Corrupt { .. } | PersistError::Decode { .. } | PersistError::ChecksumMismatch)
+    }
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistError::Io(e) => write!(f, "{}", e),
+            PersistError::NotAnIndex => write!(f, "not an index snapshot or write-ahead log"),
+            PersistError::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            PersistError::Truncated { record } => write!(f, "input ends inside record {}", record),
+            PersistError::Corrupt { record } => write!(f, "record {} does not match its checksum", record),
+            PersistError::Decode { record, message } => write!(f, "record {} is invalid: {}", record, message),
+            PersistError::ChecksumMismatch => write!(f, "snapshot checksum mismatch"),
+        }
+    }
+}
+
+impl error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PersistError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for PersistError {
+    fn from(e: io::Error) -> Self {
+        PersistError::Io(e)
+    }
+}
+
+/// What was kept and what was lost when loading a damaged snapshot or replaying a write-ahead log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// records loaded or replayed
+    pub recovered: usize,
+    /// records from the first damaged one to the end of the input
+    pub dropped: usize,
+}
+
+/// A disagreement between the trees and the items of an index, see `Index::verify`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Inconsistency {
+    /// a value of the document `key` at `path` is not in the tree
+    Missing { key: String, path: String, value: Value },
+    /// the tree holds `key` under a value its document does not have at `path`, or `key` has no document
+    Stale { key: String, path: String, value: Value },
+}
+
 impl FromStr for Op {
     type Err = UnknownOperatorError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -110,6 +223,42 @@ impl JsonPathOrder {
             ordering: ord,
         }
     }
+
+    /// Returns true when the path is a wildcard (`attrs.*` or `$**`) that indexes every scalar leaf under a subtree
+    pub fn is_wildcard(&self) -> bool {
+        wildcard_prefix(&self.path).is_some()
+    }
+}
+
+/// Returns the subtree prefix of a wildcard path, `attrs.*` gives `attrs` and `$**` gives the document root
+fn wildcard_prefix(path: &str) -> Option<&str> {
+    if path == "$**" {
+        Some("")
+    } else {
+        path.strip_suffix(".*")
+    }
+}
+
+/// Collects every scalar leaf under `v` keyed by its canonical path, array elements are indexed under the path of the array
+fn collect_leaves(keys: &mut Vec<String>, v: &Value, out: &mut Vec<(String, Value)>) {
+    match v {
+        Value::Object(m) => {
+            m.iter().for_each(|(k, child)| {
+                keys.push(k.to_string());
+                collect_leaves(keys, child, out);
+                keys.pop();
+            });
+        }
+        Value::Array(a) => {
+            a.iter().for_each(|child| collect_leaves(keys, child, out));
+        }
+        Value::Null => {}
+        _ => {
+            if !keys.is_empty() {
+                out.push((path::canonical_keys(keys), v.clone()))
+            }
+        }
+    }
 }
 
 
@@ -143,7 +292,205 @@ impl PartialOrd for FloatKey {
 
 impl Eq for FloatKey {}
 
-type MultiMap<K1, K2, V> = BTreeMap<K1, HashMap<K2, V>>;
+/// A sorted map of index keys to the keys of the documents holding them, documents are only stored in `items`
+type MultiMap<K1, K2> = BTreeMap<K1, HashSet<K2>>;
+
+enum TreeQuery {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    None,
+}
+
+/// The tree a query is answered from and the bounds of the keys it matches
+enum KeyRange {
+    Int(Bound<i64>, Bound<i64>),
+    Float(Bound<FloatKey>, Bound<FloatKey>),
+    Str(Bound<String>, Bound<String>, Option<glob::Pattern>),
+    /// every key of every tree
+    All,
+    Empty,
+}
+
+const LIKE_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Returns the key bounds of a comparison on a tree
+fn key_bounds<K: Clone>(op: &Op, q: K) -> (Bound<K>, Bound<K>) {
+    match op {
+        Op::EQ => (Included(q.clone()), Included(q)),
+        Op::LT => (Unbounded, Excluded(q)),
+        Op::GT => (Excluded(q), Unbounded),
+        Op::LIKE => (Unbounded, Unbounded),
+    }
+}
+
+/// Narrows bounds so a walk in the given direction starts at `from`
+fn narrow<K: Ord>(lower: Bound<K>, upper: Bound<K>, rev: bool, from: Option<K>) -> (Bound<K>, Bound<K>) {
+    match (from, rev) {
+        (None, _) => (lower, upper),
+        (Some(from), false) => {
+            let tighter = match &lower {
+                Included(l) | Excluded(l) => from > *l,
+                Unbounded => true
+            };
+            if tighter { (Included(from), upper) } else { (lower, upper) }
+        }
+        (Some(from), true) => {
+            let tighter = match &upper {
+                Included(u) | Excluded(u) => from < *u,
+                Unbounded => true
+            };
+            if tighter { (lower, Included(from)) } else { (lower, upper) }
+        }
+    }
+}
+
+/// Returns false for bounds `BTreeMap::range` would panic on
+fn valid_range<K: Ord>(lower: &Bound<K>, upper: &Bound<K>) -> bool {
+    match (lower, upper) {
+        (Included(l), Included(u)) => l <= u,
+        (Included(l), Excluded(u)) | (Excluded(l), Included(u)) | (Excluded(l), Excluded(u)) => l < u,
+        _ => true
+    }
+}
+
+/// Walks the buckets of a tree within bounds until `f` returns false
+fn scan_tree<K: Ord, V>(tree: &BTreeMap<K, V>, lower: Bound<K>, upper: Bound<K>, rev: bool, f: &mut dyn FnMut(&K, &V) -> bool) {
+    if !valid_range(&lower, &upper) {
+        return;
+    }
+    let range = tree.range((lower, upper));
+    if rev {
+        for (k, v) in range.rev() {
+            if !f(k, v) {
+                break;
+            }
+        }
+    } else {
+        for (k, v) in range {
+            if !f(k, v) {
+                break;
+            }
+        }
+    }
+}
+
+/// Converts a bound when its value converts, an unbounded side always converts
+fn map_bound<T, K>(b: &Bound<T>, f: impl Fn(&T) -> Option<K>) -> Option<Bound<K>> {
+    match b {
+        Included(v) => f(v).map(Included),
+        Excluded(v) => f(v).map(Excluded),
+        Unbounded => Some(Unbounded)
+    }
+}
+
+/// Returns the literal prefix of a glob pattern
+fn like_prefix(pattern: &str) -> String {
+    pattern.chars().take_while(|c| !matches!(c, '*' | '?' | '[')).collect()
+}
+
+/// Returns the smallest string greater than every string starting with `prefix`, `None` when there is none
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(c) = chars.pop() {
+        let mut next = c as u32 + 1;
+        while next <= char::MAX as u32 {
+            if let Some(n) = std::char::from_u32(next) {
+                chars.push(n);
+                return Some(chars.into_iter().collect());
+            }
+            next += 1;
+        }
+    }
+    None
+}
+
+/// Returns the tree and key bounds a query is answered from, LIKE is a range over the literal prefix of its pattern
+fn key_range(indexer: &Indexer, q: &Value, op: &Op) -> KeyRange {
+    match (tree_query(indexer, q), op) {
+        (TreeQuery::Int(_), Op::LIKE) | (TreeQuery::Float(_), Op::LIKE) | (TreeQuery::None, _) => KeyRange::Empty,
+        (TreeQuery::Int(q), op) => {
+            let (lower, upper) = key_bounds(op, q);
+            KeyRange::Int(lower, upper)
+        }
+        (TreeQuery::Float(q), op) => {
+            let (lower, upper) = key_bounds(op, FloatKey(q));
+            KeyRange::Float(lower, upper)
+        }
+        (TreeQuery::Str(q), Op::LIKE) => {
+            let glob_matcher = match glob::Pattern::new(&q) {
+                Ok(m) => { m }
+                Err(_) => {
+                    return KeyRange::Empty;
+                }
+            };
+            let prefix = like_prefix(&q);
+            let lower = if prefix.is_empty() { Unbounded } else { Included(prefix.to_string()) };
+            let upper = match prefix_successor(&prefix) {
+                Some(s) => Excluded(s),
+                None => Unbounded
+            };
+            KeyRange::Str(lower, upper, Some(glob_matcher))
+        }
+        (TreeQuery::Str(q), op) => {
+            let (lower, upper) = key_bounds(op, q);
+            KeyRange::Str(lower, upper, None)
+        }
+    }
+}
+
+/// Picks the tree a query value is looked up in
+fn tree_query(indexer: &Indexer, q: &Value) -> TreeQuery {
+    match indexer {
+        Indexer::Json(_) => {
+            if q.is_i64() {
+                TreeQuery::Int(q.as_i64().unwrap())
+            } else if q.is_f64() {
+                TreeQuery::Float(q.as_f64().unwrap())
+            } else if q.is_string() {
+                TreeQuery::Str(String::from(q.as_str().unwrap()))
+            } else {
+                TreeQuery::None
+            }
+        }
+        Indexer::Integer(_) => {
+            q.as_i64().map(TreeQuery::Int).unwrap_or(TreeQuery::None)
+        }
+        Indexer::Float(_) => {
+            q.as_f64().map(TreeQuery::Float).unwrap_or(TreeQuery::None)
+        }
+        Indexer::String(_) => {
+            q.as_str().map(|q| TreeQuery::Str(q.to_string())).unwrap_or(TreeQuery::None)
+        }
+    }
+}
+
+/// Writes a file through a temporary file next to it which is then renamed over `path`, so a crash never leaves a
+/// partially written file behind
+fn write_atomically(path: &Path, f: &mut dyn FnMut(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let mut tmp_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    {
+        let file = File::create(&tmp_path)?;
+        let mut w = BufWriter::new(file);
+        if let Err(e) = f(&mut w) {
+            drop(w);
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+    Ok(())
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 enum KeyCase {
@@ -156,29 +503,204 @@ enum KeyCase {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Index {
     pub indexer: Indexer,
-    int_tree: Arc<RwLock<HashMap<String, MultiMap<i64, String, Value>>>>,
-    str_tree: Arc<RwLock<HashMap<String, MultiMap<String, String, Value>>>>,
-    float_tree: Arc<RwLock<HashMap<String, MultiMap<FloatKey, String, Value>>>>,
-    items: Arc<RwLock<IndexMap<String, Value>>>
+    int_tree: Arc<RwLock<HashMap<String, MultiMap<i64, String>>>>,
+    str_tree: Arc<RwLock<HashMap<String, MultiMap<String, String>>>>,
+    float_tree: Arc<RwLock<HashMap<String, MultiMap<FloatKey, String>>>>,
+    items: Arc<RwLock<IndexMap<String, Value>>>,
+    /// the keys of `items` in order, for key scans. shared with `items` like them, see `Index::shared`
+    #[serde(default)]
+    keys: Arc<RwLock<BTreeSet<String>>>,
+    #[serde(default)]
+    sketches: Arc<RwLock<HashMap<String, Sketch>>>,
+    #[serde(skip)]
+    wal: Option<Arc<Mutex<Wal>>>,
+    /// set on the indexes of a `Collection`, which owns their documents and refuses changes made through them
+    #[serde(skip)]
+    shared: bool,
 }
 
 impl Index {
-    pub fn from(v: &[u8]) -> Result<Self, ()> {
-        match serde_json::from_slice::<Index>(v) {
-            Ok(s) => {
-                Ok(s)
+    /// Loads an index from a snapshot created with `to_vec`, snapshots in the older JSON format are detected and loaded too
+    pub fn from(v: &[u8]) -> Result<Self, PersistError> {
+        let snapshot = if snapshot::is_snapshot(v) {
+            snapshot::read_stream(v)
+        } else {
+            snapshot::decode_legacy(v)
+        };
+        snapshot.map(Index::from_snapshot)
+    }
+
+    /// Creates a binary snapshot of the index. only the indexer, the sketches and the items are written, the trees are
+    /// rebuilt on load
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut b = vec![];
+        self.write_to(&mut b).unwrap();
+        b
+    }
+
+    /// Writes a snapshot of the index to `w` one document at a time
+    pub fn write_to(&self, w: impl Write) -> io::Result<()> {
+        let reader = self.items.read().unwrap();
+        self.write_items(w, &reader)
+    }
+
+    fn write_items(&self, w: impl Write, items: &IndexMap<String, Value>) -> io::Result<()> {
+        let sketches = self.sketches.read().unwrap();
+        snapshot::write_stream(w, &self.indexer, &sketches, items)?.flush()
+    }
+
+    /// Reads a snapshot written with `write_to` or `to_vec` one document at a time. reads are small, so pass a buffered reader
+    pub fn read_from(r: impl Read) -> Result<Self, PersistError> {
+        snapshot::read_stream(r).map(Index::from_snapshot)
+    }
+
+    /// Reads a possibly damaged snapshot, keeping the documents written before the first truncated or corrupted record.
+    /// the report tells how many documents were loaded and how many were dropped
+    pub fn read_partial(r: impl Read) -> Result<(Self, RecoveryReport), PersistError> {
+        snapshot::read_partial(r).map(|(s, report)| {
+            let idx = Index::from_snapshot(s);
+            // the sketches were saved before the documents, they still count the ones dropped
+            if report.dropped > 0 {
+                idx.sketches.write().unwrap().values_mut().for_each(|s| s.mark_dirty());
             }
-            Err(_) => {
-                Err(())
+            (idx, report)
+        })
+    }
+
+    /// Saves a snapshot of the index to a file. the snapshot is written to a temporary file next to it which is then
+    /// renamed over `path`, so a crash never leaves a partially written snapshot behind
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_atomically(path.as_ref(), &mut |w| self.write_to(w))
+    }
+
+    /// Writes the index as a read-only file laid out for memory mapping, open it with [`MmapIndex::open`]. the file is
+    /// replaced atomically like with `save`. fails with `InvalidInput` when the index holds more documents than the
+    /// u32 doc ids of the format can address
+    pub fn save_mmap(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let items = self.items.read().unwrap();
+        let int_tree = self.int_tree.read().unwrap();
+        let float_tree = self.float_tree.read().unwrap();
+        let str_tree = self.str_tree.read().unwrap();
+        write_atomically(path.as_ref(), &mut |w| mmap::write(w, &self.indexer, &items, &int_tree, &float_tree, &str_tree))
+    }
+
+    /// Opens a snapshot saved with `save`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PersistError> {
+        let file = File::open(path)?;
+        Index::read_from(BufReader::new(file))
+    }
+
+    fn from_snapshot(s: Snapshot) -> Self {
+        let mut idx = Index::new(s.indexer);
+        idx.items = Arc::new(RwLock::new(s.items));
+        idx.build();
+        // the sketches were saved with the documents, they are set after building so the documents are not added twice
+        idx.sketches = Arc::new(RwLock::new(s.sketches));
+        idx
+    }
+
+    /// Attaches a write-ahead log at `path`, creating it when missing. the changes already in the log are replayed
+    /// on the index first, then every `insert`, `remove` and committed batch is appended to it before being applied.
+    /// A change the log fails to append is not applied, `try_insert`, `try_remove` and `Batch::try_commit` return the error.
+    /// Replay stops at the first truncated or corrupted record, which is cut off the log together with the records after it
+    pub fn attach_wal(&mut self, path: impl AsRef<Path>, policy: FsyncPolicy) -> Result<RecoveryReport, PersistError> {
+        self.writable()?;
+        let path = path.as_ref();
+        self.wal = None;
+        let (report, end) = wal::replay(path, &mut |entry| self.apply(entry))?;
+        self.wal = Some(Arc::new(Mutex::new(Wal::open(path, policy, end)?)));
+        Ok(report)
+    }
+
+    /// Stops logging changes, the log file is left as it is
+    pub fn detach_wal(&mut self) {
+        self.wal = None;
+    }
+
+    /// Flushes and fsyncs the write-ahead log. once an append failed, it is reported here and by every later change
+    pub fn sync_wal(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().sync(),
+            None => Ok(())
+        }
+    }
+
+    /// Saves a snapshot to `path` and empties the write-ahead log, whose changes are all in the snapshot
+    pub fn compact(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => {
+                // changes are logged under the items write lock, holding the read lock keeps them out until the log is emptied
+                let items = self.items.read().unwrap();
+                let mut wal = wal.lock().unwrap();
+                wal.sync()?;
+                write_atomically(path.as_ref(), &mut |w| self.write_items(w, &items))?;
+                wal.truncate()
             }
+            None => self.save(path)
         }
     }
 
-    pub fn to_vec(&self) -> Vec<u8> {
-        let b = serde_json::to_vec(&self).unwrap();
-        b
+    /// Restores an index from the snapshot at `snapshot_path` and the write-ahead log at `wal_path`, which stays
+    /// attached. a missing snapshot starts from an empty index with `indexer`. the report is the one of `attach_wal`
+    pub fn recover(snapshot_path: impl AsRef<Path>, wal_path: impl AsRef<Path>, indexer: Indexer, policy: FsyncPolicy) -> Result<(Self, RecoveryReport), PersistError> {
+        let mut idx = match Index::open(snapshot_path) {
+            Ok(idx) => idx,
+            Err(PersistError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Index::new(indexer),
+            Err(e) => return Err(e)
+        };
+        let report = idx.attach_wal(wal_path, policy)?;
+        Ok((idx, report))
     }
 
+    /// Path of the attached write-ahead log
+    pub fn wal_path(&self) -> Option<std::path::PathBuf> {
+        self.wal.as_ref().map(|wal| wal.lock().unwrap().path().to_path_buf())
+    }
+
+    /// Appends a change to the write-ahead log, callers hold the items write lock so `compact` cannot miss it
+    fn log(&self, entry: &WalEntry) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().append(entry),
+            None => Ok(())
+        }
+    }
+
+    fn apply(&mut self, entry: WalEntry) {
+        match entry {
+            WalEntry::Insert { key, value } => self.insert(&key, value),
+            WalEntry::Remove { key } => self.remove(&key),
+            entry => {
+                // replay runs before the log is attached, nothing is appended
+                let _ = self.apply_batch(entry);
+            }
+        }
+    }
+
+    fn apply_batch(&mut self, entry: WalEntry) -> io::Result<()> {
+        self.writable()?;
+        {
+            let mut collection = self.items.write().unwrap();
+            self.log(&entry)?;
+            let (inserts, updates, deletes) = match entry {
+                WalEntry::Batch { inserts, updates, deletes } => (inserts, updates, deletes),
+                _ => return Ok(())
+            };
+            inserts.iter().for_each(|(k, v)| {
+                collection.insert(k.to_string(), v.clone());
+            });
+            updates.iter().for_each(|(k, v)| {
+                if collection.contains_key(k) {
+                    collection.insert(k.to_string(), v.clone());
+                }
+            });
+            deletes.iter().for_each(|k| {
+                collection.swap_remove(k);
+            });
+        }
+        //rebuild index
+        self.build();
+        Ok(())
+    }
 }
 
 pub trait BatchTransaction<'a> {
@@ -210,13 +732,13 @@ impl<'a> Batch<'a> {
         match indexer {
             Indexer::Json(j) => {
                 let mut found = 0;
-                j.path_orders.iter().for_each(|p| {
-                    let value = v.dot_get_or(&p.path, Value::Null).unwrap_or(Value::Null);
-                    if !value.is_null() {
+                let required = j.path_orders.iter().filter(|p| !p.is_wildcard()).count();
+                j.path_orders.iter().filter(|p| !p.is_wildcard()).for_each(|p| {
+                    if !PathExpr::parse(&p.path).resolve(v).is_empty() {
                         found += 1
                     }
                 });
-                if found == j.path_orders.len() {
+                if found == required {
                     Ok((k, v))
                 } else {
                     Err(())
@@ -278,49 +800,119 @@ impl<'a> BatchTransaction<'a> for Batch<'a> {
         self.deletes.insert(k.to_string());
     }
 
+    /// Applies the batch, a batch the write-ahead log fails to append is dropped, see `try_commit`
     fn commit(&mut self) {
-        self.inserts.iter().for_each(|(k, v)| {
-            let mut collection = self.index.items.write().unwrap();
-            collection.insert(k.to_string(), v.clone());
-        });
-        self.updates.iter().for_each(|(k, v)| {
-            let mut collection = self.index.items.write().unwrap();
-            if collection.contains_key(k) {
-                collection.insert(k.to_string(), v.clone());
-            }
-        });
-        self.deletes.iter().for_each(|k| {
-            let mut collection = self.index.items.write().unwrap();
-            collection.remove(k);
-        });
+        let _ = self.try_commit();
+    }
+}
+
+impl Batch<'_> {
+    /// Applies the batch like `commit`, returns the error when the write-ahead log fails to append it or when the index
+    /// belongs to a `Collection`. the batch is then not applied and is dropped
+    pub fn try_commit(&mut self) -> io::Result<()> {
+        let inserts: Vec<(String, Value)> = self.inserts.drain().collect();
+        let updates: Vec<(String, Value)> = self.updates.drain().collect();
+        let deletes: Vec<String> = self.deletes.drain().collect();
+        let result = self.index.apply_batch(WalEntry::Batch { inserts, updates, deletes });
 
-        self.inserts.clear();
         self.inserts.shrink_to_fit();
-        self.updates.clear();
         self.updates.shrink_to_fit();
-        self.deletes.clear();
         self.deletes.shrink_to_fit();
-        //rebuild index
-        {
-            self.index.build();
+        result
+    }
+}
+
+/// A query run against the trees of an index, kept to order and explain its result
+struct Scan {
+    index: Index,
+    field: String,
+    op: Op,
+    value: Value,
+}
+
+impl Scan {
+    fn collect(&self) -> Vec<(String, Value)> {
+        self.index.query(&self.field, &self.value, &self.op).into_iter().collect()
+    }
+
+    /// Returns the walk direction when results ordered by `indexer` come out of the tree already sorted,
+    /// that is when the order is on the queried path alone
+    fn stream_direction(&self, indexer: &Indexer) -> Option<bool> {
+        let ordering = match (&self.index.indexer, indexer) {
+            (Indexer::Json(_), Indexer::Json(j)) => {
+                match j.path_orders.as_slice() {
+                    [path_order] if path::canonical(&path_order.path) == self.field => path_order.ordering,
+                    _ => return None
+                }
+            }
+            (Indexer::Integer(_), Indexer::Integer(i)) => i.ordering,
+            (Indexer::Float(_), Indexer::Float(f)) => f.ordering,
+            (Indexer::String(_), Indexer::String(s)) => s.ordering,
+            _ => return None
+        };
+        match ordering {
+            IndexOrd::ASC => Some(false),
+            IndexOrd::DESC => Some(true),
+        }
+    }
+
+    /// Puts matches in tree order by walking the tree, ties are broken by document key. the walk stops once every
+    /// match is placed. returns false and leaves the matches as they are when some of them are no longer in the
+    /// range walked, because their document changed since the query
+    fn order(&self, rev: bool, matches: &mut Vec<(String, Value)>) -> bool {
+        let mut positions: HashMap<&str, usize> = matches.iter().enumerate().map(|(i, (k, _))| (k.as_str(), i)).collect();
+        let mut order = Vec::with_capacity(matches.len());
+        self.index.scan(&self.field, &self.value, &self.op, rev, None, &mut |_, bucket| {
+            let mut keys: Vec<&String> = bucket.iter().collect();
+            keys.sort();
+            order.extend(keys.into_iter().filter_map(|k| positions.remove(k.as_str())));
+            !positions.is_empty()
+        });
+        if !positions.is_empty() {
+            return false;
         }
+        let mut taken: Vec<Option<(String, Value)>> = matches.drain(..).map(Some).collect();
+        matches.extend(order.into_iter().filter_map(|i| taken[i].take()));
+        true
     }
 }
 
+/// The result of a query, its matches are collected when the query runs. ordering on the queried path walks the
+/// tree in order instead of sorting the matches
 pub struct QueryResult {
     matches: Vec<(String, Value)>,
+    scan: Option<Scan>,
     indexer: Indexer,
     index: Index,
+    sort_time: Option<Duration>,
+    /// true when `order_by` put the matches in order by walking the tree, see `Scan::order`
+    sorted_by_tree: bool,
 }
 
 impl<'a> QueryResult {
     pub fn new(matches: Vec<(String, Value)>, indexer: Indexer) -> Self {
         QueryResult {
             matches,
+            scan: None,
+            indexer: indexer.clone(),
+            index: Index::new(indexer),
+            sort_time: None,
+            sorted_by_tree: false,
+        }
+    }
+
+    fn from_scan(scan: Scan) -> Self {
+        let indexer = scan.index.indexer.clone();
+        QueryResult {
+            matches: scan.collect(),
+            scan: Some(scan),
             indexer: indexer.clone(),
             index: Index::new(indexer),
+            sort_time: None,
+            sorted_by_tree: false,
         }
     }
+
     pub fn and_then(&'a mut self) -> &'a Index {
         //let mut new_index = Index::new(self.indexer.clone());
         for (k, v) in self.matches.iter() {
@@ -330,134 +922,566 @@ impl<'a> QueryResult {
     }
 
     pub fn count(&self) -> usize {
-        self.matches.len()
+        self.get().len()
     }
 
     pub fn order_by(&mut self, indexer: Indexer) -> OrderedResult {
+        let rev = self.scan.as_ref().and_then(|scan| scan.stream_direction(&indexer));
         self.indexer = indexer.clone();
-        self.sort();
+        let started = Instant::now();
+        self.sorted_by_tree = match (&self.scan, rev) {
+            (Some(scan), Some(rev)) => scan.order(rev, &mut self.matches),
+            _ => false
+        };
+        match self.sorted_by_tree {
+            true => self.sort_time = Some(started.elapsed()),
+            false => self.sort()
+        }
         OrderedResult {
-            matches: &mut self.matches
+            result: self,
+            indexer,
         }
     }
 
     pub fn get(&self) -> &Vec<(String, Value)> {
-        return &self.matches;
+        &self.matches
+    }
+
+    /// Aggregates the numeric values at a dot path of the matched documents, see [`Agg`]
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op, Agg};
+    /// use serde_json::{json, Value};
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"age": 21, "gpa": 3.0}));
+    /// index.insert("user.2", json!({"age": 35, "gpa": 3.5}));
+    /// index.insert("user.3", json!({"age": 16, "gpa": 2.0}));
+    /// let adults = index.find_where("age", Op::GT, 18);
+    /// assert_eq!(adults.aggregate("gpa", Agg::Avg), Some(Value::from(3.25)));
+    /// assert_eq!(adults.aggregate("age", Agg::Sum), Some(Value::from(56)));
+    /// ```
+    pub fn aggregate(&self, path: &str, agg: Agg) -> Option<Value> {
+        aggregate::documents(path, self.get().iter().map(|(_, v)| v), agg)
+    }
+
+    /// Distinct keys of a path among the matched documents, numbers in order then strings in order
+    pub fn distinct(&self, path: &str) -> Vec<Value> {
+        let keys = self.keys();
+        self.with_buckets(path, |buckets| facet::distinct(buckets, Some(&keys)))
     }
 
+    /// Counts the matched documents by the keys or the numeric ranges of several paths, see [`Facet`]. the result is
+    /// keyed by path, a facet replaces an earlier one of the same path
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op, Facet};
+    /// use serde_json::json;
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"age": 21, "state": "CA", "gpa": 3.0}));
+    /// index.insert("user.2", json!({"age": 35, "state": "NY", "gpa": 3.5}));
+    /// index.insert("user.3", json!({"age": 40, "state": "CA", "gpa": 2.0}));
+    /// let facets = index.find_where("age", Op::GT, 18).facets(&[Facet::values("state"), Facet::ranges("gpa", &[2.5, 3.25])]);
+    /// assert_eq!(serde_json::to_value(&facets).unwrap(), json!({
+    ///     "state": {"CA": 2, "NY": 1},
+    ///     "gpa": {"..2.5": 1, "2.5..3.25": 1, "3.25..": 1}
+    /// }));
+    /// ```
+    pub fn facets(&self, facets: &[Facet]) -> IndexMap<String, FacetCounts> {
+        let keys = self.keys();
+        facets.iter().map(|f| (f.path().to_string(), self.with_buckets(f.path(), |buckets| facet::counts(f, buckets, Some(&keys))))).collect()
+    }
+
+    /// Documents matched by either result. the documents of `self` come first, then those only `other` matched, or
+    /// both are sorted again when `self` was ordered with `order_by`
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op};
+    /// use serde_json::json;
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("$**", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"age": 21, "state": "CA"}));
+    /// index.insert("user.2", json!({"age": 35, "state": "NY"}));
+    /// index.insert("user.3", json!({"age": 16, "state": "NY"}));
+    /// let adults = index.find_where("age", Op::GT, 18);
+    /// let new_york = index.find_where("state", Op::EQ, "NY");
+    /// assert_eq!(adults.union(&new_york).count(), 3);
+    /// assert_eq!(adults.intersect(&new_york).get()[0].0, "user.2");
+    /// assert_eq!(adults.difference(&new_york).get()[0].0, "user.1");
+    /// assert_eq!(adults.symmetric_difference(&new_york).count(), 2);
+    /// ```
+    pub fn union(&self, other: &QueryResult) -> QueryResult {
+        let keys = self.keys();
+        let mut matches = self.get().clone();
+        matches.extend(other.get().iter().filter(|(k, _)| !keys.contains(k.as_str())).cloned());
+        self.combine(matches)
+    }
+
+    /// Documents matched by both results, in the order of `self`
+    pub fn intersect(&self, other: &QueryResult) -> QueryResult {
+        let keys = other.keys();
+        self.combine(self.get().iter().filter(|(k, _)| keys.contains(k.as_str())).cloned().collect())
+    }
+
+    /// Documents matched by `self` and not by `other`, in the order of `self`
+    pub fn difference(&self, other: &QueryResult) -> QueryResult {
+        let keys = other.keys();
+        self.combine(self.get().iter().filter(|(k, _)| !keys.contains(k.as_str())).cloned().collect())
+    }
+
+    /// Documents matched by only one of the results, ordered like `union`
+    pub fn symmetric_difference(&self, other: &QueryResult) -> QueryResult {
+        let keys = self.keys();
+        let other_keys = other.keys();
+        let mut matches: Vec<(String, Value)> = self.get().iter().filter(|(k, _)| !other_keys.contains(k.as_str())).cloned().collect();
+        matches.extend(other.get().iter().filter(|(k, _)| !keys.contains(k.as_str())).cloned());
+        self.combine(matches)
+    }
+
+    /// Returns the matched documents projected, in the order of the result, see [`Projection`]
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op, Projection};
+    /// use serde_json::json;
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"name": "Kwadwo", "age": 21, "email": "kwadwo@example.com"}));
+    /// index.insert("user.2", json!({"name": "Kwame", "age": 16}));
+    /// let adults = index.find_where("age", Op::GT, 18);
+    /// assert_eq!(adults.project(&Projection::new().include("name")), vec![("user.1".to_string(), json!({"name": "Kwadwo"}))]);
+    /// let ages = Projection::new().include("name").rename("age", "years");
+    /// assert_eq!(adults.project(&ages), vec![("user.1".to_string(), json!({"name": "Kwadwo", "years": 21}))]);
+    /// ```
+    pub fn project(&self, projection: &Projection) -> Vec<(String, Value)> {
+        self.get().iter().map(|(k, v)| (k.to_string(), projection.apply(v))).collect()
+    }
+
+    /// Joins the matched documents with the documents of another index they reference: a document references those
+    /// of `right` whose value at `right_path` equals one of its own values at `path`. the referenced values are looked
+    /// up in the trees of `right`, its other documents are not read. rows follow the order of the matches, or the one of
+    /// `order_by`, the rows of one document are ordered by the key of the referenced document
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op, Join};
+    /// use serde_json::json;
+    /// let mut users = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("id", IndexOrd::ASC)] }));
+    /// users.insert("user.1", json!({"id": 1, "name": "Kwadwo"}));
+    /// users.insert("user.2", json!({"id": 2, "name": "Kwame"}));
+    /// let mut orders = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("total", IndexOrd::ASC)] }));
+    /// orders.insert("order.1", json!({"user_id": 2, "total": 120}));
+    /// orders.insert("order.2", json!({"user_id": 3, "total": 80}));
+    /// let rows = orders.find_where("total", Op::GT, 100).join("user_id", &users, "id", Join::Inner);
+    /// assert_eq!(rows[0].key, "order.1");
+    /// assert_eq!(rows[0].right, Some(("user.2".to_string(), json!({"id": 2, "name": "Kwame"}))));
+    /// ```
+    pub fn join(&self, path: &str, right: &Index, right_path: &str, kind: Join) -> Vec<JoinRow> {
+        join::join(self.get(), path, right, right_path, kind)
+    }
+
+    /// A result holding `matches`, sorted again when `self` was ordered
+    fn combine(&self, matches: Vec<(String, Value)>) -> QueryResult {
+        let mut result = QueryResult::new(matches, self.indexer.clone());
+        if self.sort_time.is_some() {
+            result.sort();
+        }
+        result
+    }
+
+    fn keys(&self) -> HashSet<&str> {
+        self.get().iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    /// Calls `f` with the buckets of a path. they are read from the trees of the queried index when the trees answer
+    /// the path and the result has at least as many matches as the path has keys, they may then hold documents that
+    /// did not match. smaller results read the buckets from their documents
+    fn with_buckets<R>(&self, path: &str, f: impl FnOnce(&[(Value, &HashSet<String>)]) -> R) -> R {
+        let field = path::canonical(path);
+        match &self.scan {
+            Some(scan) if planner::covers(&scan.index, &field) && self.get().len() >= scan.index.path_keys(&field) => {
+                let items = scan.index.items.read().unwrap();
+                aggregate::with_buckets(&scan.index, &items, &field, f)
+            }
+            _ => aggregate::with_document_buckets(self.get().iter().map(|(k, v)| (k, v)), &field, f)
+        }
+    }
+
+    /// Returns the matches deserialized into `T`, a document that does not fit `T` is reported with its key instead
+    /// of failing the whole result
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op};
+    /// use serde::Deserialize;
+    /// use serde_json::json;
+    /// #[derive(Deserialize)]
+    /// struct User {
+    ///     name: String,
+    /// }
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"name": "Kwadwo", "age": 21}));
+    /// index.insert("user.2", json!({"age": 35}));
+    /// let users = index.find_where("age", Op::EQ, 21).get_as::<User>();
+    /// assert_eq!(users[0].as_ref().unwrap().1.name, "Kwadwo");
+    /// let broken = index.find_where("age", Op::EQ, 35).get_as::<User>();
+    /// assert_eq!(broken[0].as_ref().err().unwrap().key, "user.2");
+    /// ```
+    pub fn get_as<T: DeserializeOwned>(&self) -> Vec<Result<(String, T), DocumentError>> {
+        typed::decode(self.get())
+    }
+
+    fn get_mut(&mut self) -> &mut Vec<(String, Value)> {
+        &mut self.matches
+    }
+
+    /// Reports how the query is answered. the tree walk is repeated to count the buckets and documents it visits,
+    /// the sort time is the one of the last `order_by`, a tree walk when `sorted_by_tree` is set
+    pub fn explain(&self) -> Explain {
+        let mut explain = Explain {
+            tree: None,
+            path: None,
+            op: None,
+            lower: Unbounded,
+            upper: Unbounded,
+            like_prefix: None,
+            buckets_visited: 0,
+            documents_examined: 0,
+            documents_returned: 0,
+            scan_time: Duration::default(),
+            sort_time: self.sort_time.unwrap_or_default(),
+            sorted_by_tree: self.sorted_by_tree,
+        };
+        match &self.scan {
+            Some(scan) => {
+                let started = Instant::now();
+                let (tree, lower, upper) = match key_range(&scan.index.indexer, &scan.value, &scan.op) {
+                    KeyRange::Int(l, u) => (Some("int_tree"), map_bound(&l, |k| Some(Value::from(*k))).unwrap(), map_bound(&u, |k| Some(Value::from(*k))).unwrap()),
+                    KeyRange::Float(l, u) => (Some("float_tree"), map_bound(&l, |k| Some(Value::from(k.0))).unwrap(), map_bound(&u, |k| Some(Value::from(k.0))).unwrap()),
+                    KeyRange::Str(l, u, _) => (Some("str_tree"), map_bound(&l, |k| Some(Value::from(k.as_str()))).unwrap(), map_bound(&u, |k| Some(Value::from(k.as_str()))).unwrap()),
+                    KeyRange::All | KeyRange::Empty => (None, Unbounded, Unbounded)
+                };
+                explain.tree = tree.map(|t| t.to_string());
+                explain.path = Some(scan.field.to_string());
+                explain.op = Some(scan.op);
+                explain.lower = lower;
+                explain.upper = upper;
+                if let (Op::LIKE, Some(pattern)) = (&scan.op, scan.value.as_str()) {
+                    explain.like_prefix = Some(like_prefix(pattern));
+                }
+                scan.index.scan(&scan.field, &scan.value, &scan.op, false, None, &mut |_, bucket| {
+                    explain.buckets_visited += 1;
+                    explain.documents_examined += bucket.len();
+                    true
+                });
+                explain.documents_returned = self.get().len();
+                explain.scan_time = started.elapsed();
+            }
+            None => {
+                let returned = self.get().len();
+                explain.documents_examined = returned;
+                explain.documents_returned = returned;
+            }
+        }
+        explain
+    }
 
     fn sort(&mut self) {
         let indexer = self.indexer.clone();
-        match indexer {
-            Indexer::Json(j) => {
-                self.matches.par_sort_by(|(_, lhs), (_, rhs)| {
-                    let ordering: Vec<Ordering> = j.path_orders.iter().map(|path_order| {
-                        let lvalue = lhs.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
+        let started = Instant::now();
+        sort_matches(&indexer, self.get_mut());
+        self.sort_time = Some(started.elapsed());
+    }
+}
 
-                        let rvalue = rhs.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
-                        match (lvalue, rvalue) {
-                            (Value::String(ls), Value::String(rs)) => {
-                                match path_order.ordering {
-                                    IndexOrd::ASC => {
-                                        ls.cmp(&rs)
-                                    }
-                                    IndexOrd::DESC => {
-                                        rs.cmp(&ls)
-                                    }
-                                }
-                            }
-                            (Value::Number(ls), Value::Number(rs)) => {
-                                let ln = ls.as_f64().unwrap_or(0.0);
-                                let rn = rs.as_f64().unwrap_or(0.0);
+fn sort_matches(indexer: &Indexer, matches: &mut [(String, Value)]) {
+    matches.par_sort_by(|(lk, lhs), (rk, rhs)| {
+        compare_sort_keys(indexer, &sort_key(indexer, lhs), &sort_key(indexer, rhs)).then_with(|| lk.cmp(rk))
+    });
+}
 
-                                match path_order.ordering {
-                                    IndexOrd::ASC => {
-                                        OrderedFloat(ln).cmp(&OrderedFloat(rn))
-                                    }
-                                    IndexOrd::DESC => {
-                                        OrderedFloat(rn).cmp(&OrderedFloat(ln))
-                                    }
-                                }
+/// How a query was answered, returned by `QueryResult::explain`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Explain {
+    /// `int_tree`, `float_tree` or `str_tree`, `None` when no tree holds values of the type queried
+    pub tree: Option<String>,
+    /// canonical path of the tree, `None` for results not produced by a query
+    pub path: Option<String>,
+    pub op: Option<Op>,
+    /// bounds of the keys walked, a LIKE walks the keys starting with the literal prefix of its pattern
+    pub lower: Bound<Value>,
+    pub upper: Bound<Value>,
+    pub like_prefix: Option<String>,
+    pub buckets_visited: usize,
+    /// document keys found in the buckets visited, a document indexed under several keys is counted once per key
+    pub documents_examined: usize,
+    pub documents_returned: usize,
+    pub scan_time: Duration,
+    pub sort_time: Duration,
+    /// true when the results were ordered by walking the tree in order instead of sorting them
+    pub sorted_by_tree: bool,
+}
+
+/// Returns the values a document is ordered by
+fn sort_key(indexer: &Indexer, v: &Value) -> Vec<Value> {
+    match indexer {
+        Indexer::Json(j) => {
+            j.path_orders.iter().map(|path_order| PathExpr::parse(&path_order.path).first(v)).collect()
+        }
+        _ => {
+            vec![v.clone()]
+        }
+    }
+}
+
+fn compare_sort_keys(indexer: &Indexer, lhs: &[Value], rhs: &[Value]) -> Ordering {
+    match indexer {
+        Indexer::Json(j) => {
+            j.path_orders.iter().zip(lhs.iter().zip(rhs.iter())).fold(Ordering::Equal, |order_chain, (path_order, (lvalue, rvalue))| {
+                let ordering = match (lvalue, rvalue) {
+                    (Value::String(ls), Value::String(rs)) => {
+                        match path_order.ordering {
+                            IndexOrd::ASC => {
+                                ls.cmp(rs)
                             }
-                            _ => {
-                                Ordering::Equal
+                            IndexOrd::DESC => {
+                                rs.cmp(ls)
                             }
                         }
-                    }).collect();
-
-                    let mut itr = ordering.iter();
-                    let mut order_chain = itr.next().unwrap_or(&Ordering::Equal).to_owned();
-
-                    while let Some(t) = itr.next() {
-                        order_chain = order_chain.then(t.to_owned()).to_owned();
                     }
-                    order_chain
-                });
-            }
-            Indexer::Integer(i) => {
-                self.matches.par_sort_by(|(_, lhs), (_, rhs)| {
-                    let lvalue = lhs.as_i64().unwrap_or(0);
-                    let rvalue = rhs.as_i64().unwrap_or(0);
-                    match i.ordering {
-                        IndexOrd::ASC => {
-                            lvalue.cmp(&rvalue)
-                        }
-                        IndexOrd::DESC => {
-                            rvalue.cmp(&lvalue)
+                    (Value::Number(ls), Value::Number(rs)) => {
+                        let ln = ls.as_f64().unwrap_or(0.0);
+                        let rn = rs.as_f64().unwrap_or(0.0);
+
+                        match path_order.ordering {
+                            IndexOrd::ASC => {
+                                OrderedFloat(ln).cmp(&OrderedFloat(rn))
+                            }
+                            IndexOrd::DESC => {
+                                OrderedFloat(rn).cmp(&OrderedFloat(ln))
+                            }
                         }
                     }
-                });
+                    _ => {
+                        Ordering::Equal
+                    }
+                };
+                order_chain.then(ordering)
+            })
+        }
+        Indexer::Integer(i) => {
+            let lvalue = lhs[0].as_i64().unwrap_or(0);
+            let rvalue = rhs[0].as_i64().unwrap_or(0);
+            match i.ordering {
+                IndexOrd::ASC => {
+                    lvalue.cmp(&rvalue)
+                }
+                IndexOrd::DESC => {
+                    rvalue.cmp(&lvalue)
+                }
             }
-            Indexer::Float(f) => {
-                self.matches.par_sort_by(|(_, lhs), (_, rhs)| {
-                    let lvalue = lhs.as_f64().unwrap_or(0.0);
-                    let rvalue = rhs.as_f64().unwrap_or(0.0);
+        }
+        Indexer::Float(f) => {
+            let lvalue = lhs[0].as_f64().unwrap_or(0.0);
+            let rvalue = rhs[0].as_f64().unwrap_or(0.0);
 
-                    match f.ordering {
-                        IndexOrd::ASC => {
-                            OrderedFloat(lvalue).cmp(&OrderedFloat(rvalue))
-                        }
-                        IndexOrd::DESC => {
-                            OrderedFloat(rvalue).cmp(&OrderedFloat(lvalue))
-                        }
-                    }
-                });
+            match f.ordering {
+                IndexOrd::ASC => {
+                    OrderedFloat(lvalue).cmp(&OrderedFloat(rvalue))
+                }
+                IndexOrd::DESC => {
+                    OrderedFloat(rvalue).cmp(&OrderedFloat(lvalue))
+                }
             }
-            Indexer::String(s) => {
-                self.matches.par_sort_by(|(_, lhs), (_, rhs)| {
-                    let lvalue = lhs.as_str().unwrap_or("");
-                    let rvalue = rhs.as_str().unwrap_or("");
-                    match s.ordering {
-                        IndexOrd::ASC => {
-                            lvalue.cmp(&rvalue)
-                        }
-                        IndexOrd::DESC => {
-                            rvalue.cmp(&lvalue)
-                        }
-                    }
-                });
+        }
+        Indexer::String(s) => {
+            let lvalue = lhs[0].as_str().unwrap_or("");
+            let rvalue = rhs[0].as_str().unwrap_or("");
+            match s.ordering {
+                IndexOrd::ASC => {
+                    lvalue.cmp(rvalue)
+                }
+                IndexOrd::DESC => {
+                    rvalue.cmp(lvalue)
+                }
             }
         }
     }
 }
 
+/// An opaque position in an ordered result, made of the sort key and the document key of the last entry
+/// of a page. resuming from a cursor returns the entries strictly after that position, so entries inserted
+/// between pages are neither repeated nor skipped
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cursor {
+    key: String,
+    sort_key: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidCursorError;
+
+impl fmt::Display for InvalidCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cursor")
+    }
+}
+
+impl error::Error for InvalidCursorError {}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = serde_json::to_vec(self).map_err(|_| fmt::Error)?;
+        b.iter().try_for_each(|c| write!(f, "{:02x}", c))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = InvalidCursorError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let b = s.as_bytes().chunks(2).map(|c| {
+            std::str::from_utf8(c).ok().filter(|_| c.len() == 2).and_then(|c| u8::from_str_radix(c, 16).ok())
+        }).collect::<Option<Vec<u8>>>().ok_or(InvalidCursorError)?;
+        serde_json::from_slice(&b).map_err(|_| InvalidCursorError)
+    }
+}
+
 pub struct OrderedResult<'a> {
-    matches: &'a mut Vec<(String, Value)>,
+    result: &'a mut QueryResult,
+    indexer: Indexer,
 }
 
 impl<'a> OrderedResult<'a> {
     pub fn get(&self) -> &Vec<(String, Value)> {
-        return &self.matches;
+        self.result.get()
     }
 
     pub fn count(&self) -> usize {
-        self.matches.len()
+        self.get().len()
+    }
+
+    /// Returns the ordered matches deserialized into `T`, see `QueryResult::get_as`
+    pub fn get_as<T: DeserializeOwned>(&self) -> Vec<Result<(String, T), DocumentError>> {
+        typed::decode(self.get())
     }
 
     pub fn limit(&'a mut self, size: usize) -> &mut Self {
-        self.matches.truncate(size);
+        self.result.get_mut().truncate(size);
         self
     }
+
+    /// Skips the first `n` entries
+    pub fn skip(&mut self, n: usize) -> &mut Self {
+        let matches = self.result.get_mut();
+        let n = n.min(matches.len());
+        matches.drain(..n);
+        self
+    }
+
+    /// Keeps only the entries that come after the cursor, use it with the cursor of the previous page to resume a scan
+    pub fn after(&mut self, cursor: &Cursor) -> &mut Self {
+        let indexer = &self.indexer;
+        self.result.get_mut().retain(|(k, v)| {
+            compare_sort_keys(indexer, &sort_key(indexer, v), &cursor.sort_key).then_with(|| k.cmp(&cursor.key)) == Ordering::Greater
+        });
+        self
+    }
+
+    /// Returns the cursor of the last entry, `None` when the result is empty
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.get().last().map(|(k, v)| Cursor {
+            key: k.to_string(),
+            sort_key: sort_key(&self.indexer, v),
+        })
+    }
+}
+
+/// Borrowed matches of [`Index::iter_where`] and [`Index::range`]. holds read locks on the items and the trees, so writers
+/// on a shared index wait until it is dropped
+pub struct Matches<'a> {
+    items: RwLockReadGuard<'a, IndexMap<String, Value>>,
+    int_tree: RwLockReadGuard<'a, HashMap<String, MultiMap<i64, String>>>,
+    float_tree: RwLockReadGuard<'a, HashMap<String, MultiMap<FloatKey, String>>>,
+    str_tree: RwLockReadGuard<'a, HashMap<String, MultiMap<String, String>>>,
+    field: String,
+    range: KeyRange,
+}
+
+type Entries<'m> = Box<dyn Iterator<Item=(&'m String, &'m Value)> + 'm>;
+
+impl<'a> Matches<'a> {
+    fn new(index: &'a Index, field: String, range: KeyRange) -> Self {
+        Matches {
+            items: index.items.read().unwrap(),
+            int_tree: index.int_tree.read().unwrap(),
+            float_tree: index.float_tree.read().unwrap(),
+            str_tree: index.str_tree.read().unwrap(),
+            field,
+            range,
+        }
+    }
+
+    /// Returns the matches in key order, a document matching through several keys is returned once
+    pub fn iter(&self) -> Entries<'_> {
+        self.entries(false)
+    }
+
+    /// Returns the matches in reverse key order
+    pub fn iter_rev(&self) -> Entries<'_> {
+        self.entries(true)
+    }
+
+    fn entries(&self, rev: bool) -> Entries<'_> {
+        let mut seen = HashSet::new();
+        let keys: DocumentKeys<'_> = match &self.range {
+            KeyRange::Int(lower, upper) => {
+                buckets(self.int_tree.get(&self.field), lower, upper, rev)
+            }
+            KeyRange::Float(lower, upper) => {
+                buckets(self.float_tree.get(&self.field), lower, upper, rev)
+            }
+            KeyRange::Str(lower, upper, glob_matcher) => {
+                let glob_matcher = glob_matcher.clone();
+                match self.str_tree.get(&self.field) {
+                    Some(tree) if valid_range(lower, upper) => {
+                        let range = tree.range((lower.clone(), upper.clone()));
+                        let range: Box<dyn Iterator<Item=(&String, &HashSet<String>)>> = if rev { Box::new(range.rev()) } else { Box::new(range) };
+                        Box::new(range.filter(move |(k, _)| {
+                            match &glob_matcher {
+                                Some(m) => m.matches_with(k, LIKE_OPTIONS),
+                                None => true
+                            }
+                        }).flat_map(|(_, bucket)| bucket.iter()))
+                    }
+                    _ => Box::new(std::iter::empty())
+                }
+            }
+            KeyRange::All => {
+                let int = buckets(self.int_tree.get(&self.field), &Unbounded, &Unbounded, rev);
+                let float = buckets(self.float_tree.get(&self.field), &Unbounded, &Unbounded, rev);
+                let str = buckets(self.str_tree.get(&self.field), &Unbounded, &Unbounded, rev);
+                if rev {
+                    Box::new(str.chain(float).chain(int))
+                } else {
+                    Box::new(int.chain(float).chain(str))
+                }
+            }
+            KeyRange::Empty => Box::new(std::iter::empty())
+        };
+        let items = &self.items;
+        Box::new(keys.filter(move |k| seen.insert(*k)).filter_map(move |k| items.get_key_value(k)))
+    }
+}
+
+type DocumentKeys<'m> = Box<dyn Iterator<Item=&'m String> + 'm>;
+
+/// Returns the document keys of the buckets of a tree within bounds
+fn buckets<'m, K: Ord + Clone>(tree: Option<&'m MultiMap<K, String>>, lower: &Bound<K>, upper: &Bound<K>, rev: bool) -> DocumentKeys<'m> {
+    match tree {
+        Some(tree) if valid_range(lower, upper) => {
+            let range = tree.range((lower.clone(), upper.clone()));
+            if rev {
+                Box::new(range.rev().flat_map(|(_, bucket)| bucket.iter()))
+            } else {
+                Box::new(range.flat_map(|(_, bucket)| bucket.iter()))
+            }
+        }
+        _ => Box::new(std::iter::empty())
+    }
 }
 
 impl<'a> Index {
@@ -477,90 +1501,75 @@ impl<'a> Index {
             int_tree: Arc::new(RwLock::new(HashMap::new())),
             str_tree: Arc::new(RwLock::new(HashMap::new())),
             float_tree: Arc::new(RwLock::new(HashMap::new())),
+            keys: Arc::new(RwLock::new(BTreeSet::new())),
+            sketches: Arc::new(RwLock::new(HashMap::new())),
+            wal: None,
+            shared: false,
         };
         idx.build();
         idx
     }
 
-    /// Inserts a new entry or overrides a previous entry in the index
+    /// Inserts a new entry or overrides a previous entry in the index. an entry the write-ahead log fails to append
+    /// is not inserted, nor is one inserted through an index of a `Collection`, see `try_insert`
     pub fn insert<V>(&mut self, key: &str, value: V) where V: Serialize + Deserialize<'a> {
+        let _ = self.try_insert(key, value);
+    }
+
+    /// Inserts like `insert`, returns the error when the write-ahead log fails to append the entry, which is then not
+    /// inserted, or when the index belongs to a `Collection`. with `FsyncPolicy::Always` the entry is durable once this returns `Ok`
+    pub fn try_insert<V>(&mut self, key: &str, value: V) -> io::Result<()> where V: Serialize + Deserialize<'a> {
+        self.writable()?;
         let k = key.to_string();
         let v = serde_json::to_value(value).unwrap();
         match self.filter(&k, &v) {
             Ok(e) => {
-                let mut collection = self.items.write().unwrap();
                 let (key, v) = e;
-                collection.insert(key.to_string(), v.clone());
-                let indexer = self.indexer.clone();
-                match indexer {
-                    Indexer::Json(j) => {
-                        j.path_orders.iter().for_each(|path_order| {
-                            let value: Value = v.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
-                            if value.is_i64() {
-                                self.insert_int_index(&path_order.path, &value, key, v)
-                            } else if value.is_f64() {
-                                self.insert_float_index(&path_order.path, &value, key, v)
-                            } else if value.is_string() {
-                                self.insert_string_index(&path_order.path, &value, key, v)
-                            }
-                        });
-                    }
-                    Indexer::Integer(_) => {
-                        let value: Value = v.clone();
-                        self.insert_int_index("*", &value, key, v);
-                    }
-                    Indexer::Float(_) => {
-                        let value: Value = v.clone();
-                        self.insert_float_index("*", &value, key, v)
-                    }
-                    Indexer::String(_) => {
-                        let value: Value = v.clone();
-                        self.insert_string_index("*", &value, key, v)
+                let mut collection = self.items.write().unwrap();
+                self.log(&WalEntry::Insert { key: key.to_string(), value: v.clone() })?;
+                let previous = match collection.insert(key.to_string(), v.clone()) {
+                    Some(previous) => self.index_entries(&previous),
+                    None => {
+                        self.keys.write().unwrap().insert(key.to_string());
+                        vec![]
                     }
-                }
+                };
+                self.move_entries(key, &previous, &self.index_entries(v));
+                Ok(())
             }
-            Err(_) => {}
+            Err(_) => Ok(())
         }
     }
 
-    /// Removes an entry from the index
+    /// Removes an entry from the index. an entry the write-ahead log fails to append the removal of is kept, as is one
+    /// removed through an index of a `Collection`, see `try_remove`
     pub fn remove(&mut self, k: &str) {
+        let _ = self.try_remove(k);
+    }
+
+    /// Removes like `remove`, returns the error when the write-ahead log fails to append the removal, the entry is then
+    /// kept, or when the index belongs to a `Collection`
+    pub fn try_remove(&mut self, k: &str) -> io::Result<()> {
+        self.writable()?;
         let mut write_side = self.items.write().unwrap();
+        if write_side.contains_key(k) {
+            self.log(&WalEntry::Remove { key: k.to_string() })?;
+        }
 
         let v: Value = match write_side.swap_remove(k) {
             Some(v) => {
                 v
             }
             None => {
-                return;
+                return Ok(());
             }
         };
+        self.keys.write().unwrap().remove(k);
         drop(write_side);
 
-        let indexer = self.indexer.clone();
-        match indexer {
-            Indexer::Json(j) => {
-                j.path_orders.iter().for_each(|path_order| {
-                    let value: Value = v.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
-                    if value.is_i64() {} else if value.is_f64() {} else if value.is_string() {
-                        self.remove_string_index(&path_order.path, &value, k)
-                    }
-                })
-            }
-            Indexer::Integer(_) => {
-                let value: Value = v.clone();
-                self.remove_int_index("*", &value, k)
-            }
-            Indexer::Float(_) => {
-                let value: Value = v.clone();
-                self.remove_float_index("*", &value, k)
-            }
-            Indexer::String(_) => {
-                let value: Value = v.clone();
-                self.remove_string_index("*", &value, k)
-            }
-        }
+        self.move_entries(k, &self.index_entries(&v), &[]);
         //self.build()
+        Ok(())
     }
 
     /// Batch transaction on the index. you can insert/update/delete multiple entries with one operation by commit the operation with ```b.commit()```
@@ -602,6 +1611,9 @@ impl<'a> Index {
     /// - `gt` Greater than
     /// - `like` Check for match using Glob style pattern matching
     ///
+    /// The field can be a dot path `address.city`, a JSON Pointer `/address/city` or a JSONPath `$.address.city`,
+    /// they all address the same tree.
+    ///
     /// ## Example
     ///  ```rust
     ///   let query = students_index.find_where("state", Op::EQ, "CA");
@@ -610,195 +1622,212 @@ impl<'a> Index {
     ///
     pub fn find_where<V>(&self, field: &str, op: Op, value: V) -> QueryResult where V: Serialize + Deserialize<'a> {
         let value = serde_json::to_value(value).unwrap();
-        let indexer = self.indexer.clone();
-        let matches = match &indexer {
-            Indexer::Json(_) => {
-                if value.is_i64() {
-                    let q = value.as_i64().unwrap();
-                    self.query_int_index(field, q, op)
-                } else if value.is_f64() {
-                    let q = value.as_f64().unwrap();
-                    self.query_float_index(field, q, op)
-                } else if value.is_string() {
-                    let q = String::from(value.as_str().unwrap());
-                    self.query_string_index(field, q, op)
+        let field = path::canonical(field);
+        QueryResult::from_scan(Scan {
+            index: self.clone(),
+            field,
+            op,
+            value,
+        })
+    }
+
+    /// Returns the matches of a query without cloning them. the trees stay read locked until the returned [`Matches`] is dropped,
+    /// iterate them with `iter()` and the usual iterator adaptors
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexInt, IndexOrd, Op};
+    /// let mut ages_index = Index::new(Indexer::Integer(IndexInt { ordering: IndexOrd::ASC }));
+    /// ages_index.insert("user.1", 21);
+    /// ages_index.insert("user.2", 35);
+    /// let matches = ages_index.iter_where("*", Op::GT, 18);
+    /// let keys: Vec<&String> = matches.iter().filter(|(_, v)| v.as_i64() < Some(30)).map(|(k, _)| k).take(10).collect();
+    /// assert_eq!(keys, vec!["user.1"]);
+    /// ```
+    pub fn iter_where<V>(&self, field: &str, op: Op, value: V) -> Matches<'_> where V: Serialize + Deserialize<'a> {
+        let value = serde_json::to_value(value).unwrap();
+        let range = key_range(&self.indexer, &value, &op);
+        Matches::new(self, path::canonical(field), range)
+    }
+
+    /// Returns the entries whose key on `field` falls within `range`, in key order, without cloning them.
+    /// a fully unbounded range walks the integer, float and string trees one after the other
+    pub fn range<V>(&self, field: &str, range: impl RangeBounds<V>) -> Matches<'_> where V: Serialize {
+        let bound = |b: Bound<&V>| match b {
+            Included(v) => Included(tree_query(&self.indexer, &serde_json::to_value(v).unwrap_or(Value::Null))),
+            Excluded(v) => Excluded(tree_query(&self.indexer, &serde_json::to_value(v).unwrap_or(Value::Null))),
+            Unbounded => Unbounded
+        };
+        let range = match (bound(range.start_bound()), bound(range.end_bound())) {
+            (Unbounded, Unbounded) => KeyRange::All,
+            (lower, upper) => {
+                let as_int = |b: &Bound<TreeQuery>| map_bound(b, |q| if let TreeQuery::Int(i) = q { Some(*i) } else { None });
+                let as_float = |b: &Bound<TreeQuery>| map_bound(b, |q| if let TreeQuery::Float(f) = q { Some(FloatKey(*f)) } else { None });
+                let as_str = |b: &Bound<TreeQuery>| map_bound(b, |q| if let TreeQuery::Str(s) = q { Some(s.to_string()) } else { None });
+                if let (Some(l), Some(u)) = (as_int(&lower), as_int(&upper)) {
+                    KeyRange::Int(l, u)
+                } else if let (Some(l), Some(u)) = (as_float(&lower), as_float(&upper)) {
+                    KeyRange::Float(l, u)
+                } else if let (Some(l), Some(u)) = (as_str(&lower), as_str(&upper)) {
+                    KeyRange::Str(l, u, None)
                 } else {
-                    HashMap::new()
+                    KeyRange::Empty
                 }
             }
-            Indexer::Integer(_) => {
-                let q = value.as_i64().unwrap();
-                self.query_int_index(field, q, op)
-            }
-            Indexer::Float(_) => {
-                let q = value.as_f64().unwrap();
-                self.query_float_index(field, q, op)
-            }
-            Indexer::String(_) => {
-                let q = String::from(value.as_str().unwrap());
-                self.query_string_index(field, q, op)
-            }
         };
-        let matches = matches.into_iter().map(|(k, v)| { (k, v) }).collect();
-        QueryResult::new(matches, indexer)
-    }
-
-    fn query_int_index(&self, key: &str, q: i64, op: Op) -> HashMap<String, Value> {
-        let empty_map = MultiMap::new();
-        let empty_matches: HashMap<String, Value> = HashMap::new();
-        let read_guard = self.int_tree.read().unwrap();
-        let int_tree_reader = read_guard.get(key).unwrap_or(&empty_map);
-        match op {
-            Op::EQ => {
-                let m = int_tree_reader.get(&q).unwrap_or(&empty_matches);
-                m.clone()
-            }
-            Op::LT => {
-                let mut matches: HashMap<String, Value> = HashMap::new();
-                int_tree_reader.range(..q).for_each(|(k, v)| {
-                    if k.lt(&q) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                    }
-                });
-                matches
-            }
-            Op::GT => {
-                let mut matches: HashMap<String, Value> = HashMap::new();
-                int_tree_reader.range(q..).for_each(|(k, v)| {
-                    if k.gt(&q) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                    }
-                });
-                matches
-            }
-            Op::LIKE => { HashMap::new() }
+        Matches::new(self, path::canonical(field), range)
+    }
+
+    /// Answers a boolean query, conditions on paths of the indexer are looked up in the trees and the others are
+    /// checked against the documents. see [`Query`]
+    pub fn find(&self, q: &Query) -> QueryResult {
+        self.planner().find(q)
+    }
+
+    /// Returns how `find` answers a query
+    pub fn plan(&self, q: &Query) -> Plan {
+        self.planner().plan(q)
+    }
+
+    fn planner(&self) -> planner::Planner<'_> {
+        planner::Planner { items: &self.items, indexes: vec![("default", self)] }
+    }
+
+    /// Returns the matches of a query, a document matching through several keys is returned once
+    fn query(&self, field: &str, q: &Value, op: &Op) -> HashMap<String, Value> {
+        let mut matches: HashMap<String, Value> = HashMap::new();
+        let items = self.items.read().unwrap();
+        self.scan(field, q, op, false, None, &mut |_, bucket| {
+            matches.extend(bucket.iter().filter_map(|k| items.get(k).map(|v| (k.to_string(), v.clone()))));
+            true
+        });
+        matches
+    }
+
+    /// Walks the buckets matching a query in key order, or reverse key order when `rev` is set, until `f` returns false.
+    /// `from` narrows the walk to the keys at or after that key in walking order. callers resolving documents must hold
+    /// the `items` read lock before calling, writers lock `items` before the trees
+    /// Number of keys of a path in the integer, float and string trees together
+    pub(crate) fn path_keys(&self, field: &str) -> usize {
+        self.int_tree.read().unwrap().get(field).map_or(0, |tree| tree.len())
+            + self.float_tree.read().unwrap().get(field).map_or(0, |tree| tree.len())
+            + self.str_tree.read().unwrap().get(field).map_or(0, |tree| tree.len())
+    }
+
+    /// Number of keys of the tree `scan` walks for a condition on `field`
+    pub(crate) fn tree_keys(&self, field: &str, q: &Value, op: &Op) -> usize {
+        match key_range(&self.indexer, q, op) {
+            KeyRange::Int(..) => self.int_tree.read().unwrap().get(field).map_or(0, |tree| tree.len()),
+            KeyRange::Float(..) => self.float_tree.read().unwrap().get(field).map_or(0, |tree| tree.len()),
+            KeyRange::Str(..) => self.str_tree.read().unwrap().get(field).map_or(0, |tree| tree.len()),
+            KeyRange::All | KeyRange::Empty => 0
         }
     }
-    fn query_float_index(&self, key: &str, q: f64, op: Op) -> HashMap<String, Value> {
-        let empty_map = MultiMap::new();
-        let read_guard = self.float_tree.read().unwrap();
-        let float_tree_reader = read_guard.get(key).unwrap_or(&empty_map);
-        let empty_matches: HashMap<String, Value> = HashMap::new();
-        match op {
-            Op::EQ => {
-                float_tree_reader.get(&FloatKey(OrderedFloat(q).0)).unwrap_or(&empty_matches).clone()
+
+    fn scan(&self, field: &str, q: &Value, op: &Op, rev: bool, from: Option<&Value>, f: &mut dyn FnMut(&Value, &HashSet<String>) -> bool) {
+        let empty_int_map = MultiMap::new();
+        let empty_float_map = MultiMap::new();
+        let empty_str_map = MultiMap::new();
+        match key_range(&self.indexer, q, op) {
+            KeyRange::Int(lower, upper) => {
+                let read_guard = self.int_tree.read().unwrap();
+                let int_tree_reader = read_guard.get(field).unwrap_or(&empty_int_map);
+                let (lower, upper) = narrow(lower, upper, rev, from.and_then(|v| v.as_i64()));
+                scan_tree(int_tree_reader, lower, upper, rev, &mut |k, bucket| f(&Value::from(*k), bucket));
             }
-            Op::LT => {
-                let mut matches: HashMap<String, Value> = HashMap::new();
-                float_tree_reader.range((Unbounded, Included(FloatKey(q)))).for_each(|(k, v)| {
-                    if OrderedFloat(k.0).lt(&OrderedFloat(q)) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                    }
-                });
-                matches
+            KeyRange::Float(lower, upper) => {
+                let read_guard = self.float_tree.read().unwrap();
+                let float_tree_reader = read_guard.get(field).unwrap_or(&empty_float_map);
+                let (lower, upper) = narrow(lower, upper, rev, from.and_then(|v| v.as_f64()).map(FloatKey));
+                scan_tree(float_tree_reader, lower, upper, rev, &mut |k, bucket| f(&Value::from(k.0), bucket));
             }
-            Op::GT => {
-                let mut matches: HashMap<String, Value> = HashMap::new();
-                float_tree_reader.range((Included(FloatKey(q)), Unbounded)).for_each(|(k, v)| {
-                    if OrderedFloat(k.0).gt(&OrderedFloat(q)) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
+            KeyRange::Str(lower, upper, glob_matcher) => {
+                let read_guard = self.str_tree.read().unwrap();
+                let str_tree_reader = read_guard.get(field).unwrap_or(&empty_str_map);
+                let (lower, upper) = narrow(lower, upper, rev, from.and_then(|v| v.as_str()).map(|s| s.to_string()));
+                scan_tree(str_tree_reader, lower, upper, rev, &mut |k, bucket| {
+                    match &glob_matcher {
+                        Some(m) if !m.matches_with(k, LIKE_OPTIONS) => true,
+                        _ => f(&Value::from(k.as_str()), bucket)
                     }
                 });
-                matches
             }
-            Op::LIKE => { HashMap::new() }
+            KeyRange::All | KeyRange::Empty => {}
         }
     }
-    fn query_string_index(&self, key: &str, q: String, op: Op) -> HashMap<String, Value> {
-        let empty_map = MultiMap::new();
-        let empty_matches: HashMap<String, Value> = HashMap::new();
-        let read_guard = self.str_tree.read().unwrap();
-        let str_tree_reader = read_guard.get(key).unwrap_or(&empty_map);
-        match op {
-            Op::EQ => {
-                str_tree_reader.get(&q).unwrap_or(&empty_matches).clone()
-            }
-            Op::LT => {
-                let mut matches = HashMap::new();
-                str_tree_reader.range(..q.to_owned()).for_each(|(k, v)| {
-                    if k.lt(&q) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                    }
-                });
-                matches
-            }
-            Op::GT => {
-                let mut matches = HashMap::new();
-                str_tree_reader.range(q.to_owned()..).for_each(|(k, v)| {
-                    if k.gt(&q) {
-                        matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                    }
-                });
-                matches
-            }
-            Op::LIKE => {
-
-                let options = glob::MatchOptions {
-                    case_sensitive: true,
-                    require_literal_separator: false,
-                    require_literal_leading_dot: false,
-                };
-                let glob_matcher = match glob::Pattern::new(&q) {
-                    Ok(m) => { m }
-                    Err(_) => {
-                        return HashMap::new();
-                    }
-                };
 
-                let mut prefix = String::new();
-                for c in q.chars() {
-                    match c {
-                        '*' | '?' | '[' => {
-                            break;
+    /// Returns the (tree path, value) pairs a document is indexed under. wildcard paths expand to one pair per
+    /// scalar leaf, so their trees are created on the fly
+    fn index_entries(&self, v: &Value) -> Vec<(String, Value)> {
+        match &self.indexer {
+            Indexer::Json(j) => {
+                let mut entries = vec![];
+                j.path_orders.iter().for_each(|path_order| {
+                    match wildcard_prefix(&path_order.path) {
+                        Some("") => {
+                            collect_leaves(&mut vec![], v, &mut entries)
                         }
-                        c => {
-                            prefix.push(c);
+                        Some(prefix) => {
+                            let subtree: Value = v.dot_get_or(prefix, Value::Null).unwrap_or(Value::Null);
+                            let mut keys = prefix.split('.').map(|k| k.to_string()).collect();
+                            collect_leaves(&mut keys, &subtree, &mut entries)
+                        }
+                        None => {
+                            let path = PathExpr::parse(&path_order.path);
+                            let field = path.canonical();
+                            path.resolve(v).into_iter().for_each(|value| {
+                                entries.push((field.to_string(), value))
+                            });
                         }
                     }
-                }
-
-                let (lb, fchar) = if prefix.is_empty() {
-                    (Unbounded, None)
-                }else {
-                    (Included(prefix.to_string()), prefix.chars().nth(0))
-                };
+                });
+                entries
+            }
+            _ => {
+                vec![("*".to_string(), v.clone())]
+            }
+        }
+    }
 
-                if lb == Unbounded {
-                    let mut matches = HashMap::new();
-                    str_tree_reader.iter().for_each(|(k,v)|{
-                        if glob_matcher.matches_with(k, options) {
-                            matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                        }
-                    });
-                    matches
-                }
-                else {
-                    let mut matches = HashMap::new();
-                    for (k,v) in str_tree_reader.range((lb,Unbounded)) {
-                        let c2 = k.chars().nth(0);
-                        match (fchar,c2)  {
-                            (Some(c),Some(c2)) => {
-                                let c1 = c as u32;
-                                let c2 = c2 as u32;
-                                if c2 > c1 {
-                                    break;
-                                }
-                            }
-                            _ => {}
-                        }
+    fn insert_entry(&self, field: &str, value: &Value, k: &str) {
+        if value.is_i64() {
+            self.insert_int_index(field, value, k)
+        } else if value.is_f64() {
+            self.insert_float_index(field, value, k)
+        } else if value.is_string() {
+            self.insert_string_index(field, value, k)
+        }
+    }
 
-                        if glob_matcher.matches_with(k, options) {
-                            matches.extend(v.iter().map(|(k, v)| { (k.to_string(), v.clone()) }));
-                        }
-                    }
-                    matches
-                }
+    fn remove_entry(&self, field: &str, value: &Value, k: &str) {
+        if value.is_i64() {
+            self.remove_int_index(field, value, k)
+        } else if value.is_f64() {
+            self.remove_float_index(field, value, k)
+        } else if value.is_string() {
+            self.remove_string_index(field, value, k)
+        }
+    }
 
-            }
+    /// Brings the sketches up to date with the entries a document lost and gained, taking their lock once. a sketch
+    /// cannot delete a value, so a lost entry marks its sketch dirty
+    fn update_sketches(&self, removed: &[&(String, Value)], added: &[&(String, Value)]) {
+        if self.sketches.read().unwrap().is_empty() {
+            return;
         }
+        let mut sketches = self.sketches.write().unwrap();
+        removed.iter().for_each(|(field, _)| {
+            if let Some(sketch) = sketches.get_mut(field) {
+                sketch.mark_dirty()
+            }
+        });
+        added.iter().for_each(|(field, value)| {
+            if let Some(sketch) = sketches.get_mut(field).filter(|sketch| !sketch.is_dirty()) {
+                sketch.add(value)
+            }
+        });
     }
 
-    fn insert_int_index(&self, field: &str, iv: &Value, k: &str, v: &Value) {
+    fn insert_int_index(&self, field: &str, iv: &Value, k: &str) {
         let mut int_tree_writer = self.int_tree.write().unwrap();
 
         let key = iv.as_i64().unwrap();
@@ -808,31 +1837,31 @@ impl<'a> Index {
                 let mut m = MultiMap::new();
                 match m.get_mut(&key) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(key, new_map);
                         int_tree_writer.insert(field.to_string(), m);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 }
             }
             Some(m) => {
                 match m.get_mut(&key) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(key, new_map);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 }
             }
         };
     }
-    fn insert_float_index(&self, field: &str, iv: &Value, k: &str, v: &Value) {
+    fn insert_float_index(&self, field: &str, iv: &Value, k: &str) {
         let mut float_tree_writer = self.float_tree.write().unwrap();
         let key = iv.as_f64().unwrap();
         match float_tree_writer.get_mut(field) {
@@ -840,31 +1869,31 @@ impl<'a> Index {
                 let mut m = MultiMap::new();
                 match m.get_mut(&FloatKey(key)) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(FloatKey(key), new_map);
                         float_tree_writer.insert(field.to_string(), m);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 };
             }
             Some(m) => {
                 match m.get_mut(&FloatKey(key)) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(FloatKey(key), new_map);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 }
             }
         };
     }
-    fn insert_string_index(&self, field: &str, iv: &Value, k: &str, v: &Value) {
+    fn insert_string_index(&self, field: &str, iv: &Value, k: &str) {
         let mut str_tree_writer = self.str_tree.write().unwrap();
         let key = String::from(iv.as_str().unwrap());
         match str_tree_writer.get_mut(field) {
@@ -872,25 +1901,25 @@ impl<'a> Index {
                 let mut m = MultiMap::new();
                 match m.get_mut(&key) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(key, new_map);
                         str_tree_writer.insert(field.to_string(), m);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 }
             }
             Some(m) => {
                 match m.get_mut(&key) {
                     None => {
-                        let mut new_map = HashMap::new();
-                        new_map.insert(k.to_string(), v.clone());
+                        let mut new_map = HashSet::new();
+                        new_map.insert(k.to_string());
                         m.insert(key, new_map);
                     }
                     Some(b) => {
-                        b.insert(k.to_string(), v.clone());
+                        b.insert(k.to_string());
                     }
                 }
             }
@@ -901,7 +1930,7 @@ impl<'a> Index {
     fn remove_int_index(&self, field: &str, iv: &Value, k: &str) {
         let mut int_tree_writer = self.int_tree.write().unwrap();
         let key = iv.as_i64().unwrap();
-        let mut empty_map = HashMap::new();
+        let mut empty_map = HashSet::new();
         match int_tree_writer.get_mut(field) {
             None => {}
             Some(m) => {
@@ -916,7 +1945,7 @@ impl<'a> Index {
     fn remove_float_index(&self, field: &str, iv: &Value, k: &str) {
         let mut float_tree_writer = self.float_tree.write().unwrap();
         let key = iv.as_f64().unwrap();
-        let mut empty_map = HashMap::new();
+        let mut empty_map = HashSet::new();
         match float_tree_writer.get_mut(field) {
             None => {}
             Some(m) => {
@@ -932,7 +1961,7 @@ impl<'a> Index {
         let mut str_tree_writer = self.str_tree.write().unwrap();
         let key = iv.as_str().unwrap();
 
-        let mut empty_map = HashMap::new();
+        let mut empty_map = HashSet::new();
         match str_tree_writer.get_mut(field) {
             None => {}
             Some(m) => {
@@ -946,42 +1975,61 @@ impl<'a> Index {
     }
 
     fn filter(&mut self, k: &'a String, v: &'a Value) -> Result<(&'a String, &'a Value), ()> {
+        if self.accepts(v) {
+            Ok((k, v))
+        } else {
+            Err(())
+        }
+    }
+
+    /// True when the indexer indexes the document, json indexers require every non wildcard path to be present
+    fn accepts(&self, v: &Value) -> bool {
         match &self.indexer {
             Indexer::Json(j) => {
-                let mut found = 0;
-                j.path_orders.iter().for_each(|p| {
-                    let value = v.dot_get_or(&p.path, Value::Null).unwrap_or(Value::Null);
-                    if !value.is_null() {
-                        found += 1
-                    }
-                });
-                if found == j.path_orders.len() {
-                    Ok((k, v))
-                } else {
-                    Err(())
-                }
-            }
-            Indexer::Integer(_) => {
-                if v.is_i64() {
-                    Ok((k, v))
-                } else {
-                    Err(())
-                }
-            }
-            Indexer::Float(_) => {
-                if v.is_f64() {
-                    Ok((k, v))
-                } else {
-                    Err(())
-                }
-            }
-            Indexer::String(_) => {
-                if v.is_string() {
-                    Ok((k, v))
-                } else {
-                    Err(())
-                }
+                j.path_orders.iter().filter(|p| !p.is_wildcard()).all(|p| !PathExpr::parse(&p.path).resolve(v).is_empty())
             }
+            Indexer::Integer(_) => v.is_i64(),
+            Indexer::Float(_) => v.is_f64(),
+            Indexer::String(_) => v.is_string(),
+        }
+    }
+
+    /// Moves the tree entries of a document from its previous value to its current one, values the indexer does not
+    /// accept have no entries. used by indexes sharing their items with a [`Collection`], which writes the items and
+    /// their keys itself
+    fn reindex(&self, k: &str, previous: Option<&Value>, current: Option<&Value>) {
+        let entries = |v: Option<&Value>| v.filter(|v| self.accepts(v)).map(|v| self.index_entries(v)).unwrap_or_default();
+        self.move_entries(k, &entries(previous), &entries(current));
+    }
+
+    /// Replaces the tree entries of a document. entries it keeps are left alone, so replacing a document only marks
+    /// dirty the sketches of the paths whose values changed
+    fn move_entries(&self, k: &str, previous: &[(String, Value)], current: &[(String, Value)]) {
+        let id = |(field, value): &(String, Value)| (field.to_string(), value.to_string());
+        let kept: HashSet<(String, String)> = current.iter().map(id).collect();
+        let mut seen: HashSet<(String, String)> = previous.iter().map(id).collect();
+        let removed: Vec<&(String, Value)> = previous.iter().filter(|e| !kept.contains(&id(e))).collect();
+        let added: Vec<&(String, Value)> = current.iter().filter(|e| seen.insert(id(e))).collect();
+        removed.iter().for_each(|(field, value)| self.remove_entry(field, value, k));
+        added.iter().for_each(|(field, value)| self.insert_entry(field, value, k));
+        self.update_sketches(&removed, &added);
+    }
+
+    /// Creates an index over items and their ordered keys owned by someone else, only the trees belong to the index
+    fn shared(indexer: Indexer, items: Arc<RwLock<IndexMap<String, Value>>>, keys: Arc<RwLock<BTreeSet<String>>>) -> Self {
+        let mut idx = Index::new(indexer);
+        idx.items = items;
+        idx.keys = keys;
+        idx.shared = true;
+        idx.build();
+        idx
+    }
+
+    /// Fails for the indexes of a `Collection`, a change made through one of them would not reach the others
+    fn writable(&self) -> io::Result<()> {
+        match self.shared {
+            true => Err(io::Error::new(io::ErrorKind::PermissionDenied, "the index belongs to a collection, change its documents through the collection")),
+            false => Ok(())
         }
     }
 
@@ -1005,9 +2053,10 @@ impl<'a> Index {
             Indexer::Json(j) => {
                 self.items.write().unwrap().par_sort_by(|_, lhs, _, rhs| {
                     let ordering: Vec<Ordering> = j.path_orders.iter().map(|path_order| {
-                        let lvalue = lhs.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
+                        let path = PathExpr::parse(&path_order.path);
+                        let lvalue = path.first(lhs);
 
-                        let rvalue = rhs.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
+                        let rvalue = path.first(rhs);
                         match (lvalue, rvalue) {
                             (Value::String(ls), Value::String(rs)) => {
                                 match path_order.ordering {
@@ -1097,6 +2146,9 @@ impl<'a> Index {
         //self.rs.clone_from(reader.deref()
 
         let reader = self.items.read().unwrap();
+        *self.keys.write().unwrap() = reader.keys().cloned().collect();
+        // the sketches are rebuilt on their next read instead of locking them for every entry of the parallel build
+        self.sketches.write().unwrap().values_mut().for_each(|s| s.mark_dirty());
 
         {
             let mut int_tree_writer = self.int_tree.write().unwrap();
@@ -1108,35 +2160,235 @@ impl<'a> Index {
         }
 
 
-        reader.par_iter().for_each(|(k, v)| {
-            let indexer = self.indexer.clone();
-            match indexer {
-                Indexer::Json(j) => {
-                    j.path_orders.iter().for_each(|path_order| {
-                        let value: Value = v.dot_get_or(&path_order.path, Value::Null).unwrap_or(Value::Null);
-                        if value.is_i64() {
-                            self.insert_int_index(&path_order.path, &value, k, v)
-                        } else if value.is_f64() {
-                            self.insert_float_index(&path_order.path, &value, k, v)
-                        } else if value.is_string() {
-                            self.insert_string_index(&path_order.path, &value, k, v)
-                        }
-                    })
+        reader.par_iter().filter(|(_, v)| self.accepts(v)).for_each(|(k, v)| {
+            self.index_entries(v).iter().for_each(|(field, value)| {
+                self.insert_entry(field, value, k)
+            });
+        });
+    }
+
+    /// Aggregates the numeric values at a dot path of every document, see [`Agg`]. paths the trees answer are
+    /// aggregated without reading the documents, min and max only read the first and last keys of the trees
+    pub fn aggregate(&self, path: &str, agg: Agg) -> Option<Value> {
+        aggregate::index(self, path, agg)
+    }
+
+    /// Groups the documents by the keys of a path, each group has a document count and the aggregates added with
+    /// `GroupBy::aggregate`
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd, Agg};
+    /// use serde_json::{json, Value};
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)] }));
+    /// index.insert("user.1", json!({"state": "NY", "gpa": 3.0}));
+    /// index.insert("user.2", json!({"state": "CA", "gpa": 3.5}));
+    /// index.insert("user.3", json!({"state": "NY", "gpa": 2.0}));
+    /// let groups = index.group_by("state").aggregate("gpa", Agg::Sum).order_by_count(IndexOrd::DESC).get();
+    /// assert_eq!((groups[0].key.clone(), groups[0].count), (Value::from("NY"), 2));
+    /// assert_eq!(groups[0].aggregates, vec![Some(Value::from(5.0))]);
+    /// ```
+    pub fn group_by(&self, path: &str) -> GroupBy<'_> {
+        GroupBy::new(self, path)
+    }
+
+    /// Distinct keys of a path, numbers in order then strings in order. paths the trees answer are read from the
+    /// trees
+    pub fn distinct(&self, path: &str) -> Vec<Value> {
+        let items = self.items.read().unwrap();
+        aggregate::with_buckets(self, &items, &path::canonical(path), |buckets| facet::distinct(buckets, None))
+    }
+
+    /// Counts every document by the keys or the numeric ranges of several paths, see [`Facet`] and
+    /// `QueryResult::facets`
+    pub fn facets(&self, facets: &[Facet]) -> IndexMap<String, FacetCounts> {
+        let items = self.items.read().unwrap();
+        facets.iter().map(|f| {
+            let counts = aggregate::with_buckets(self, &items, &path::canonical(f.path()), |buckets| facet::counts(f, buckets, None));
+            (f.path().to_string(), counts)
+        }).collect()
+    }
+
+    /// Splits the numeric values of a path into ranges and counts the values in each, see [`Histogram`]. the counts
+    /// are the sizes of the buckets of the trees taken in key order
+    pub fn histogram(&self, path: &str, histogram: Histogram) -> Vec<HistogramBucket> {
+        histogram::histogram(self, path, histogram)
+    }
+
+    /// Returns the nearest-rank percentile of the numeric values of a path, `p` goes from 0 to 1
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd};
+    /// use serde_json::{json, Value};
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("latency", IndexOrd::ASC)] }));
+    /// for i in 1..=100 {
+    ///     index.insert(&format!("request.{}", i), json!({"latency": i}));
+    /// }
+    /// assert_eq!(index.percentile("latency", 0.5), Some(Value::from(50)));
+    /// assert_eq!(index.percentiles("latency", &[0.95, 0.99]), vec![Some(Value::from(95)), Some(Value::from(99))]);
+    /// ```
+    pub fn percentile(&self, path: &str, p: f64) -> Option<Value> {
+        histogram::percentiles(self, path, &[p]).pop().flatten()
+    }
+
+    /// Returns several percentiles of a path in one walk of its keys, see `percentile`
+    pub fn percentiles(&self, path: &str, ps: &[f64]) -> Vec<Option<Value>> {
+        histogram::percentiles(self, path, ps)
+    }
+
+    /// Returns the document stored under `key`
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.items.read().unwrap().get(key).cloned()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.items.read().unwrap().contains_key(key)
+    }
+
+    /// Returns the documents whose key is within `range`, in key order, nothing when the range is inverted. keys
+    /// compare as strings, so `"student:10".."student:20"` holds `student:100` but not `student:9`
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd};
+    /// use serde_json::json;
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    /// index.insert("user.2", json!({"age": 35}));
+    /// index.insert("admin.1", json!({"age": 40}));
+    /// index.insert("user.1", json!({"age": 21}));
+    /// let keys = |docs: Vec<(String, serde_json::Value)>| docs.into_iter().map(|(k, _)| k).collect::<Vec<String>>();
+    /// assert_eq!(keys(index.scan_prefix("user.")), vec!["user.1", "user.2"]);
+    /// assert_eq!(keys(index.scan_keys("admin.1".."user.2")), vec!["admin.1", "user.1"]);
+    /// assert_eq!(index.get("user.1"), Some(json!({"age": 21})));
+    /// ```
+    pub fn scan_keys<'r>(&self, range: impl RangeBounds<&'r str>) -> Vec<(String, Value)> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        if !valid_range(&bounds.0, &bounds.1) {
+            return vec![];
+        }
+        let items = self.items.read().unwrap();
+        let keys = self.keys.read().unwrap();
+        keys.range::<str, _>(bounds).filter_map(|k| items.get(k).map(|v| (k.to_string(), v.clone()))).collect()
+    }
+
+    /// Returns the documents whose key starts with `prefix`, in key order
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, Value)> {
+        match prefix_successor(prefix) {
+            Some(upper) => self.scan_keys((Included(prefix), Excluded(upper.as_str()))),
+            None => self.scan_keys((Included(prefix), Unbounded))
+        }
+    }
+
+    /// Keeps a [`Sketch`] of an indexed path: an approximate distinct count of its keys and approximate quantiles of
+    /// its numeric values. the sketch is updated on every insert and persisted in snapshots. the HyperLogLog and the
+    /// t-digest behind it cannot delete a value, so removing a document, changing its value at the path or committing
+    /// a batch marks the sketch dirty, and the next read rebuilds it from the trees in time linear in the distinct keys
+    /// of the path. replacing a document without changing its values at the path costs nothing
+    /// ## Example
+    /// ```rust
+    /// use indexer::{Index, Indexer, IndexJson, JsonPathOrder, IndexOrd};
+    /// use serde_json::json;
+    /// let mut index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("latency", IndexOrd::ASC)] }));
+    /// index.add_sketch("latency");
+    /// for i in 0..1000 {
+    ///     index.insert(&format!("request.{}", i), json!({"latency": i % 500}));
+    /// }
+    /// let sketch = index.sketch("latency").unwrap();
+    /// assert!((495..=505).contains(&sketch.distinct_count()));
+    /// assert!((sketch.quantile(0.5).unwrap() - 250.0).abs() < 10.0);
+    /// ```
+    pub fn add_sketch(&mut self, path: &str) {
+        let field = path::canonical(path);
+        let sketch = self.rebuild_sketch(&field);
+        self.sketches.write().unwrap().insert(field, sketch);
+    }
+
+    /// Stops keeping the sketch of a path, returns false when there was none
+    pub fn drop_sketch(&mut self, path: &str) -> bool {
+        self.sketches.write().unwrap().remove(&path::canonical(path)).is_some()
+    }
+
+    /// Returns the sketch of a path added with `add_sketch`
+    pub fn sketch(&self, path: &str) -> Option<Sketch> {
+        let field = path::canonical(path);
+        let mut sketches = self.sketches.write().unwrap();
+        let sketch = sketches.get_mut(&field)?;
+        if sketch.is_dirty() {
+            *sketch = self.rebuild_sketch(&field);
+        }
+        Some(sketch.clone())
+    }
+
+    /// Paths having a sketch
+    pub fn sketch_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.sketches.read().unwrap().keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    fn rebuild_sketch(&self, field: &str) -> Sketch {
+        let int_tree = self.int_tree.read().unwrap();
+        let float_tree = self.float_tree.read().unwrap();
+        let str_tree = self.str_tree.read().unwrap();
+        Sketch::rebuild(int_tree.get(field), float_tree.get(field), str_tree.get(field))
+    }
+
+    /// Returns per-path statistics of the trees: distinct keys, documents, min and max key, bucket sizes and an
+    /// approximate memory footprint
+    pub fn stats(&self) -> IndexStats {
+        stats::collect(self)
+    }
+
+    /// Checks the trees against the items. every indexed value of every document must be in its tree, and every
+    /// document key in a tree must belong to a document having that value. an empty result means the index is consistent
+    pub fn verify(&self) -> Vec<Inconsistency> {
+        let items = self.items.read().unwrap();
+        let int_tree = self.int_tree.read().unwrap();
+        let float_tree = self.float_tree.read().unwrap();
+        let str_tree = self.str_tree.read().unwrap();
+        let entries: HashMap<&String, Vec<(String, Value)>> = items.iter().filter(|(_, v)| self.accepts(v)).map(|(k, v)| (k, self.index_entries(v))).collect();
+        let mut found = vec![];
+
+        entries.iter().for_each(|(k, entries)| {
+            entries.iter().for_each(|(path, value)| {
+                let indexed = if let Some(i) = value.as_i64() {
+                    int_tree.get(path).and_then(|m| m.get(&i)).is_some_and(|b| b.contains(*k))
+                } else if let Some(f) = value.as_f64() {
+                    float_tree.get(path).and_then(|m| m.get(&FloatKey(f))).is_some_and(|b| b.contains(*k))
+                } else if let Some(s) = value.as_str() {
+                    str_tree.get(path).and_then(|m| m.get(s)).is_some_and(|b| b.contains(*k))
+                } else {
+                    true
+                };
+                if !indexed {
+                    found.push(Inconsistency::Missing { key: k.to_string(), path: path.to_string(), value: value.clone() });
                 }
-                Indexer::Integer(_) => {
-                    let value: Value = v.clone();
-                    self.insert_int_index("*", &value, k, v)
+            })
+        });
+
+        let has = |k: &String, path: &String, matches: &dyn Fn(&Value) -> bool| {
+            entries.get(k).is_some_and(|entries| entries.iter().any(|(p, v)| p == path && matches(v)))
+        };
+        int_tree.iter().for_each(|(path, tree)| {
+            tree.iter().for_each(|(i, bucket)| bucket.iter().for_each(|k| {
+                if !has(k, path, &|v| v.is_i64() && v.as_i64() == Some(*i)) {
+                    found.push(Inconsistency::Stale { key: k.to_string(), path: path.to_string(), value: Value::from(*i) });
                 }
-                Indexer::Float(_) => {
-                    let value: Value = v.clone();
-                    self.insert_float_index("*", &value, k, v)
+            }))
+        });
+        float_tree.iter().for_each(|(path, tree)| {
+            tree.iter().for_each(|(f, bucket)| bucket.iter().for_each(|k| {
+                if !has(k, path, &|v| v.is_f64() && v.as_f64() == Some(f.0)) {
+                    found.push(Inconsistency::Stale { key: k.to_string(), path: path.to_string(), value: Value::from(f.0) });
                 }
-                Indexer::String(_) => {
-                    let value: Value = v.clone();
-                    self.insert_string_index("*", &value, k, v)
+            }))
+        });
+        str_tree.iter().for_each(|(path, tree)| {
+            tree.iter().for_each(|(s, bucket)| bucket.iter().for_each(|k| {
+                if !has(k, path, &|v| v.as_str() == Some(s)) {
+                    found.push(Inconsistency::Stale { key: k.to_string(), path: path.to_string(), value: Value::from(s.as_str()) });
                 }
-            }
+            }))
         });
+        found
     }
 
     pub fn get_items(&self) -> Vec<(String, Value)> {
@@ -0,0 +1,345 @@
+//! Read-only index files laid out for memory mapping.
+//!
+//! The trees are written as per-path arrays of fixed width entries sorted by key, each pointing at the ids of the
+//! documents holding that key. Queries binary search the mapped arrays and only decode the documents they return, so
+//! opening a file costs the same whatever its size.
+//!
+//! ```text
+//! magic      4 bytes  "JIDM"
+//! version    u8       1
+//! documents  [key utf-8][CBOR document] per item
+//! doc table  [u64 key offset][u32 key length][u64 document offset][u32 document length] per item
+//! trees      per path: postings [u32 doc id].., strings, entries sorted by key
+//!            int    [i64 key][u64 postings offset][u64 postings length]
+//!            float  [f64 key][u64 postings offset][u64 postings length]
+//!            string [u64 key offset][u64 key length][u64 postings offset][u64 postings length]
+//! directory  CBOR     indexer, document count and the location of every tree
+//! footer     [u64 directory offset][u32 directory length][u32 crc32 of the directory]["JIDM"]
+//! ```
+
+use indexmap::map::IndexMap;
+use memmap2::Mmap;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+use std::path::Path;
+use crate::{Indexer, MultiMap, FloatKey, KeyRange, Op, PersistError, QueryResult, LIKE_OPTIONS, key_range, path};
+
+const MAGIC: &[u8; 4] = b"JIDM";
+const VERSION: u8 = 1;
+const FOOTER_LEN: usize = 8 + 4 + 4 + 4;
+const DOC_ENTRY_LEN: usize = 24;
+const NUM_ENTRY_LEN: usize = 24;
+const STR_ENTRY_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+enum TreeKind {
+    Int,
+    Float,
+    Str,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tree {
+    path: String,
+    kind: TreeKind,
+    entries: u64,
+    count: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Directory {
+    indexer: Indexer,
+    documents: u64,
+    doc_table: u64,
+    trees: Vec<Tree>,
+}
+
+/// Tracks the offset of everything written
+struct Counter<'w, W: Write> {
+    w: &'w mut W,
+    pos: u64,
+}
+
+impl<'w, W: Write> Counter<'w, W> {
+    fn put(&mut self, b: &[u8]) -> io::Result<()> {
+        self.w.write_all(b)?;
+        self.pos += b.len() as u64;
+        Ok(())
+    }
+
+    fn put_postings(&mut self, bucket: &HashSet<String>, ids: &HashMap<&str, u32>) -> io::Result<(u64, u64)> {
+        let mut postings: Vec<u32> = bucket.iter().filter_map(|k| ids.get(k.as_str()).copied()).collect();
+        postings.sort_unstable();
+        let offset = self.pos;
+        for id in postings.iter() {
+            self.put(&id.to_le_bytes())?;
+        }
+        Ok((offset, postings.len() as u64))
+    }
+}
+
+/// Converts a count or length to the u32 of the format, an index too large for it is an error instead of a wrong lookup
+fn fits_u32(n: usize, what: &str) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} {} too large for the memory mapped format", what, n)))
+}
+
+pub(crate) fn write<W: Write>(w: &mut W, indexer: &Indexer, items: &IndexMap<String, Value>, int_tree: &HashMap<String, MultiMap<i64, String>>, float_tree: &HashMap<String, MultiMap<FloatKey, String>>, str_tree: &HashMap<String, MultiMap<String, String>>) -> io::Result<()> {
+    // doc ids are u32, fail before writing anything
+    fits_u32(items.len(), "documents")?;
+    let mut out = Counter { w, pos: 0 };
+    out.put(MAGIC)?;
+    out.put(&[VERSION])?;
+
+    let mut doc_table = Vec::with_capacity(items.len() * DOC_ENTRY_LEN);
+    let mut buf = vec![];
+    for (k, v) in items.iter() {
+        buf.clear();
+        ciborium::ser::into_writer(v, &mut buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        doc_table.extend_from_slice(&out.pos.to_le_bytes());
+        doc_table.extend_from_slice(&fits_u32(k.len(), "key length")?.to_le_bytes());
+        out.put(k.as_bytes())?;
+        doc_table.extend_from_slice(&out.pos.to_le_bytes());
+        doc_table.extend_from_slice(&fits_u32(buf.len(), "document length")?.to_le_bytes());
+        out.put(&buf)?;
+    }
+    let doc_table_offset = out.pos;
+    out.put(&doc_table)?;
+
+    let ids: HashMap<&str, u32> = items.keys().enumerate().map(|(i, k)| fits_u32(i, "documents").map(|i| (k.as_str(), i))).collect::<io::Result<_>>()?;
+    let mut trees = vec![];
+    for (path, tree) in int_tree.iter() {
+        let mut entries = Vec::with_capacity(tree.len() * NUM_ENTRY_LEN);
+        for (key, bucket) in tree.iter() {
+            let (offset, len) = out.put_postings(bucket, &ids)?;
+            entries.extend_from_slice(&key.to_le_bytes());
+            entries.extend_from_slice(&offset.to_le_bytes());
+            entries.extend_from_slice(&len.to_le_bytes());
+        }
+        trees.push(Tree { path: path.to_string(), kind: TreeKind::Int, entries: out.pos, count: tree.len() as u64 });
+        out.put(&entries)?;
+    }
+    for (path, tree) in float_tree.iter() {
+        let mut entries = Vec::with_capacity(tree.len() * NUM_ENTRY_LEN);
+        for (key, bucket) in tree.iter() {
+            let (offset, len) = out.put_postings(bucket, &ids)?;
+            entries.extend_from_slice(&key.0.to_le_bytes());
+            entries.extend_from_slice(&offset.to_le_bytes());
+            entries.extend_from_slice(&len.to_le_bytes());
+        }
+        trees.push(Tree { path: path.to_string(), kind: TreeKind::Float, entries: out.pos, count: tree.len() as u64 });
+        out.put(&entries)?;
+    }
+    for (path, tree) in str_tree.iter() {
+        let mut entries = Vec::with_capacity(tree.len() * STR_ENTRY_LEN);
+        for (key, bucket) in tree.iter() {
+            let (offset, len) = out.put_postings(bucket, &ids)?;
+            entries.extend_from_slice(&out.pos.to_le_bytes());
+            entries.extend_from_slice(&(key.len() as u64).to_le_bytes());
+            entries.extend_from_slice(&offset.to_le_bytes());
+            entries.extend_from_slice(&len.to_le_bytes());
+            out.put(key.as_bytes())?;
+        }
+        trees.push(Tree { path: path.to_string(), kind: TreeKind::Str, entries: out.pos, count: tree.len() as u64 });
+        out.put(&entries)?;
+    }
+
+    let directory = Directory { indexer: indexer.clone(), documents: items.len() as u64, doc_table: doc_table_offset, trees };
+    buf.clear();
+    ciborium::ser::into_writer(&directory, &mut buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let directory_offset = out.pos;
+    out.put(&buf)?;
+    out.put(&directory_offset.to_le_bytes())?;
+    out.put(&fits_u32(buf.len(), "directory length")?.to_le_bytes())?;
+    out.put(&crc32fast::hash(&buf).to_le_bytes())?;
+    out.put(MAGIC)?;
+    out.w.flush()
+}
+
+/// A read-only index memory mapped from a file written with `Index::save_mmap`.
+/// Only the directory of the file is decoded on open, queries read the sorted key arrays in place
+/// ## Example
+/// ```rust
+/// use indexer::{Index, Indexer, IndexString, IndexOrd, MmapIndex, Op};
+/// let mut names = Index::new(Indexer::String(IndexString { ordering: IndexOrd::ASC }));
+/// names.insert("user.1", "Kwadwo");
+/// names.insert("user.2", "Kwame");
+/// let path = std::env::temp_dir().join(format!("names-{}.jidm", std::process::id()));
+/// names.save_mmap(&path).unwrap();
+/// let mapped = MmapIndex::open(&path).unwrap();
+/// assert_eq!(mapped.find_where("*", Op::LIKE, "Kwa*").count(), 2);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct MmapIndex {
+    pub indexer: Indexer,
+    map: Mmap,
+    documents: usize,
+    doc_table: usize,
+    trees: HashMap<(String, TreeKind), (usize, usize)>,
+}
+
+impl MmapIndex {
+    /// Maps an index file. the file must not be modified while it is mapped, `Index::save_mmap` replaces files
+    /// by renaming so mapped readers keep the previous version
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PersistError> {
+        let file = File::open(path)?;
+        // the mapping is read-only and files are only ever replaced by rename, never written in place
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < 5 + FOOTER_LEN || &map[..4] != MAGIC || &map[map.len() - 4..] != MAGIC {
+            return Err(PersistError::NotAnIndex);
+        }
+        if map[4] != VERSION {
+            return Err(PersistError::UnsupportedVersion(map[4]));
+        }
+        let footer = &map[map.len() - FOOTER_LEN..];
+        let directory_offset = u64_at(footer, 0) as usize;
+        let directory_len = u32_at(footer, 8) as usize;
+        let directory = map.get(directory_offset..directory_offset.saturating_add(directory_len))
+            .filter(|_| directory_offset + directory_len <= map.len() - FOOTER_LEN)
+            .ok_or(PersistError::Truncated { record: 0 })?;
+        if crc32fast::hash(directory) != u32_at(footer, 12) {
+            return Err(PersistError::Corrupt { record: 0 });
+        }
+        let directory: Directory = ciborium::de::from_reader(directory).map_err(|e| PersistError::Decode { record: 0, message: e.to_string() })?;
+
+        let fits = |offset: u64, count: u64, width: usize| {
+            count.checked_mul(width as u64).and_then(|len| len.checked_add(offset)).is_some_and(|end| end <= directory_offset as u64)
+        };
+        if !fits(directory.doc_table, directory.documents, DOC_ENTRY_LEN) {
+            return Err(PersistError::Corrupt { record: 0 });
+        }
+        let mut trees = HashMap::new();
+        for tree in directory.trees.iter() {
+            let width = if tree.kind == TreeKind::Str { STR_ENTRY_LEN } else { NUM_ENTRY_LEN };
+            if !fits(tree.entries, tree.count, width) {
+                return Err(PersistError::Corrupt { record: 0 });
+            }
+            trees.insert((tree.path.to_string(), tree.kind), (tree.entries as usize, tree.count as usize));
+        }
+        Ok(MmapIndex {
+            indexer: directory.indexer,
+            documents: directory.documents as usize,
+            doc_table: directory.doc_table as usize,
+            trees,
+            map,
+        })
+    }
+
+    /// Number of documents
+    pub fn size(&self) -> usize {
+        self.documents
+    }
+
+    /// Runs a query like `Index::find_where`, a document matching through several keys is returned once.
+    /// matches are returned in key order
+    pub fn find_where<V>(&self, field: &str, op: Op, value: V) -> QueryResult where V: Serialize {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        let field = path::canonical(field);
+        let mut ids = vec![];
+        match key_range(&self.indexer, &value, &op) {
+            KeyRange::Int(lower, upper) => {
+                if let Some(&(entries, count)) = self.trees.get(&(field, TreeKind::Int)) {
+                    let key = |i: usize| i64::from_le_bytes(self.map[entries + i * NUM_ENTRY_LEN..][..8].try_into().unwrap());
+                    let (start, end) = span(count, &lower, &upper, &key);
+                    (start..end).for_each(|i| self.postings(entries + i * NUM_ENTRY_LEN + 8, &mut ids));
+                }
+            }
+            KeyRange::Float(lower, upper) => {
+                if let Some(&(entries, count)) = self.trees.get(&(field, TreeKind::Float)) {
+                    let key = |i: usize| FloatKey(f64::from_le_bytes(self.map[entries + i * NUM_ENTRY_LEN..][..8].try_into().unwrap()));
+                    let (start, end) = span(count, &lower, &upper, &key);
+                    (start..end).for_each(|i| self.postings(entries + i * NUM_ENTRY_LEN + 8, &mut ids));
+                }
+            }
+            KeyRange::Str(lower, upper, glob_matcher) => {
+                if let Some(&(entries, count)) = self.trees.get(&(field, TreeKind::Str)) {
+                    let key = |i: usize| {
+                        let entry = entries + i * STR_ENTRY_LEN;
+                        self.str_at(u64_at(&self.map, entry) as usize, u64_at(&self.map, entry + 8) as usize)
+                    };
+                    let (start, end) = span(count, &lower.as_ref().map(|s| s.as_str()), &upper.as_ref().map(|s| s.as_str()), &key);
+                    (start..end).for_each(|i| {
+                        match &glob_matcher {
+                            Some(m) if !m.matches_with(key(i), LIKE_OPTIONS) => {}
+                            _ => self.postings(entries + i * STR_ENTRY_LEN + 16, &mut ids)
+                        }
+                    });
+                }
+            }
+            KeyRange::All | KeyRange::Empty => {}
+        }
+        let mut seen = HashSet::new();
+        let matches = ids.into_iter().filter(|id| seen.insert(*id)).filter_map(|id| self.document(id)).collect();
+        QueryResult::new(matches, self.indexer.clone())
+    }
+
+    /// Appends the document ids of the postings referenced at `at`
+    fn postings(&self, at: usize, ids: &mut Vec<u32>) {
+        let offset = u64_at(&self.map, at) as usize;
+        let len = u64_at(&self.map, at + 8) as usize;
+        if let Some(postings) = self.map.get(offset..offset.saturating_add(len.saturating_mul(4))) {
+            ids.extend(postings.chunks(4).map(|id| u32::from_le_bytes(id.try_into().unwrap())));
+        }
+    }
+
+    /// Decodes a document, `None` when its id or location is out of range
+    fn document(&self, id: u32) -> Option<(String, Value)> {
+        let id = id as usize;
+        if id >= self.documents {
+            return None;
+        }
+        let entry = self.doc_table + id * DOC_ENTRY_LEN;
+        let key = self.str_at(u64_at(&self.map, entry) as usize, u32_at(&self.map, entry + 8) as usize);
+        let offset = u64_at(&self.map, entry + 12) as usize;
+        let len = u32_at(&self.map, entry + 20) as usize;
+        let doc = self.map.get(offset..offset.saturating_add(len))?;
+        ciborium::de::from_reader(doc).ok().map(|v| (key.to_string(), v))
+    }
+
+    /// Reads a string, out of range or invalid strings read as empty
+    fn str_at(&self, offset: usize, len: usize) -> &str {
+        self.map.get(offset..offset.saturating_add(len)).and_then(|b| std::str::from_utf8(b).ok()).unwrap_or("")
+    }
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+/// Returns the positions of the first key within `lower` and of the first key past `upper` in a sorted array of `count`
+/// keys read with `key`
+fn span<K: Ord>(count: usize, lower: &Bound<K>, upper: &Bound<K>, key: &dyn Fn(usize) -> K) -> (usize, usize) {
+    let start = match lower {
+        Included(l) => partition_point(count, &|i| key(i) < *l),
+        Excluded(l) => partition_point(count, &|i| key(i) <= *l),
+        Unbounded => 0
+    };
+    let end = match upper {
+        Included(u) => partition_point(count, &|i| key(i) <= *u),
+        Excluded(u) => partition_point(count, &|i| key(i) < *u),
+        Unbounded => count
+    };
+    (start, end.max(start))
+}
+
+/// Returns the first index in `0..count` for which `pred` is false, `pred` must be true then false over the range
+fn partition_point(count: usize, pred: &dyn Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
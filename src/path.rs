@@ -0,0 +1,314 @@
+//! Path resolution for index paths and `find_where` field names.
+//!
+//! Three syntaxes are accepted:
+//! - dot paths `address.city` resolved with `json_dotpath`
+//! - RFC 6901 JSON Pointers `/address/city`, which can address keys containing dots `/attrs/a.b`
+//! - a JSONPath subset `$.items[*].sku`, `$['a.b']`, `$.items[0]` and recursive descent `$..id`
+//!
+//! Every path has a canonical name which is used as the tree key, so the same location addressed with
+//! different syntaxes shares one tree. Paths made only of plain keys canonicalize to a dot path, anything
+//! else to a normalized JSONPath like `$['attrs']['a.b']`.
+
+use serde_json::Value;
+use json_dotpath::DotPaths;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Selector {
+    Key(String),
+    Index(i64),
+    Wildcard,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Step {
+    descendant: bool,
+    selector: Selector,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PathExpr {
+    Dot(String),
+    Pointer(Vec<String>),
+    JsonPath(Vec<Step>),
+}
+
+impl PathExpr {
+    pub(crate) fn parse(path: &str) -> Self {
+        if path.starts_with('/') {
+            PathExpr::Pointer(parse_pointer(path))
+        } else if path.starts_with('$') && path != "$**" {
+            match parse_json_path(path) {
+                Some(steps) => PathExpr::JsonPath(steps),
+                None => PathExpr::Dot(path.to_string())
+            }
+        } else {
+            PathExpr::Dot(path.to_string())
+        }
+    }
+
+    /// Returns every value the path addresses in `v`. dot paths and pointers address at most one value,
+    /// JSONPath wildcards and recursive descent can address many
+    pub(crate) fn resolve(&self, v: &Value) -> Vec<Value> {
+        match self {
+            PathExpr::Dot(path) => {
+                let value: Value = v.dot_get_or(path, Value::Null).unwrap_or(Value::Null);
+                if value.is_null() {
+                    vec![]
+                } else {
+                    vec![value]
+                }
+            }
+            PathExpr::Pointer(tokens) => {
+                let mut current = v;
+                for token in tokens {
+                    let next = match current {
+                        Value::Object(m) => m.get(token),
+                        Value::Array(a) => token.parse::<usize>().ok().and_then(|i| a.get(i)),
+                        _ => None
+                    };
+                    match next {
+                        Some(n) => current = n,
+                        None => return vec![]
+                    }
+                }
+                if current.is_null() {
+                    vec![]
+                } else {
+                    vec![current.clone()]
+                }
+            }
+            PathExpr::JsonPath(steps) => {
+                select(steps, v).into_iter().filter(|v| !v.is_null()).cloned().collect()
+            }
+        }
+    }
+
+    /// Returns the first value the path addresses in `v` or `Value::Null`
+    pub(crate) fn first(&self, v: &Value) -> Value {
+        self.resolve(v).into_iter().next().unwrap_or(Value::Null)
+    }
+
+    /// Returns the object keys leading to the single location the path addresses, `None` for JSONPaths with
+    /// wildcards, array indexes or recursive descent
+    pub(crate) fn keys(&self) -> Option<Vec<String>> {
+        match self {
+            PathExpr::Dot(path) => Some(path.split('.').map(|k| k.to_string()).collect()),
+            PathExpr::Pointer(tokens) => Some(tokens.clone()),
+            PathExpr::JsonPath(steps) => steps.iter().map(|s| match &s.selector {
+                Selector::Key(k) if !s.descendant => Some(k.to_string()),
+                _ => None
+            }).collect()
+        }
+    }
+
+    pub(crate) fn canonical(&self) -> String {
+        match self {
+            PathExpr::Dot(path) => path.to_string(),
+            PathExpr::Pointer(tokens) => canonical_keys(tokens),
+            PathExpr::JsonPath(steps) => {
+                let plain = steps.iter().all(|s| {
+                    !s.descendant && match &s.selector {
+                        Selector::Key(k) => is_plain_key(k),
+                        Selector::Index(i) => *i >= 0,
+                        Selector::Wildcard => false
+                    }
+                });
+                if plain && !steps.is_empty() {
+                    steps.iter().map(|s| match &s.selector {
+                        Selector::Key(k) => k.to_string(),
+                        Selector::Index(i) => i.to_string(),
+                        Selector::Wildcard => unreachable!()
+                    }).collect::<Vec<String>>().join(".")
+                } else {
+                    let mut out = String::from("$");
+                    steps.iter().for_each(|s| {
+                        if s.descendant {
+                            out.push_str("..")
+                        }
+                        match &s.selector {
+                            Selector::Key(k) => out.push_str(&quote(k)),
+                            Selector::Index(i) => out.push_str(&format!("[{}]", i)),
+                            Selector::Wildcard => out.push_str("[*]")
+                        }
+                    });
+                    out
+                }
+            }
+        }
+    }
+}
+
+/// Returns the tree key of a path
+pub(crate) fn canonical(path: &str) -> String {
+    PathExpr::parse(path).canonical()
+}
+
+/// Returns the canonical name of a location given as a list of object keys
+pub(crate) fn canonical_keys(keys: &[String]) -> String {
+    if !keys.is_empty() && keys.iter().all(|k| is_plain_key(k)) {
+        keys.join(".")
+    } else {
+        let mut out = String::from("$");
+        keys.iter().for_each(|k| out.push_str(&quote(k)));
+        out
+    }
+}
+
+fn is_plain_key(k: &str) -> bool {
+    !k.is_empty()
+        && !k.starts_with('$')
+        && !k.starts_with('/')
+        && !k.chars().any(|c| matches!(c, '.' | '[' | ']' | '*' | '\'' | '"' | '\\'))
+}
+
+fn quote(k: &str) -> String {
+    format!("['{}']", k.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn parse_pointer(path: &str) -> Vec<String> {
+    path.split('/').skip(1).map(|t| t.replace("~1", "/").replace("~0", "~")).collect()
+}
+
+fn parse_json_path(path: &str) -> Option<Vec<Step>> {
+    let chars: Vec<char> = path.chars().collect();
+    let mut steps = vec![];
+    let mut i = 1;
+    while i < chars.len() {
+        let mut descendant = false;
+        match chars[i] {
+            '.' => {
+                i += 1;
+                if i < chars.len() && chars[i] == '.' {
+                    descendant = true;
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return None;
+                }
+                if chars[i] == '[' {
+                    if !descendant {
+                        return None;
+                    }
+                    let (selector, next) = parse_bracket(&chars, i)?;
+                    steps.push(Step { descendant, selector });
+                    i = next;
+                } else {
+                    let start = i;
+                    while i < chars.len() && chars[i] != '.' && chars[i] != '[' {
+                        i += 1;
+                    }
+                    let name: String = chars[start..i].iter().collect();
+                    let selector = if name == "*" {
+                        Selector::Wildcard
+                    } else if name.is_empty() {
+                        return None;
+                    } else {
+                        Selector::Key(name)
+                    };
+                    steps.push(Step { descendant, selector });
+                }
+            }
+            '[' => {
+                let (selector, next) = parse_bracket(&chars, i)?;
+                steps.push(Step { descendant, selector });
+                i = next;
+            }
+            _ => return None
+        }
+    }
+    Some(steps)
+}
+
+/// Parses a bracket selector starting at `chars[i] == '['`, returns the selector and the index after `]`
+fn parse_bracket(chars: &[char], i: usize) -> Option<(Selector, usize)> {
+    let mut i = i + 1;
+    match chars.get(i)? {
+        '\'' | '"' => {
+            let quote = chars[i];
+            i += 1;
+            let mut key = String::new();
+            loop {
+                match chars.get(i)? {
+                    '\\' => {
+                        key.push(*chars.get(i + 1)?);
+                        i += 2;
+                    }
+                    c if *c == quote => {
+                        i += 1;
+                        break;
+                    }
+                    c => {
+                        key.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            if *chars.get(i)? != ']' {
+                return None;
+            }
+            Some((Selector::Key(key), i + 1))
+        }
+        _ => {
+            let start = i;
+            while *chars.get(i)? != ']' {
+                i += 1;
+            }
+            let inner: String = chars[start..i].iter().collect();
+            let inner = inner.trim();
+            let selector = if inner == "*" {
+                Selector::Wildcard
+            } else {
+                Selector::Index(inner.parse::<i64>().ok()?)
+            };
+            Some((selector, i + 1))
+        }
+    }
+}
+
+fn select<'v>(steps: &[Step], v: &'v Value) -> Vec<&'v Value> {
+    let mut current = vec![v];
+    for step in steps {
+        let mut next = vec![];
+        for node in current {
+            if step.descendant {
+                let mut nodes = vec![];
+                descendants(node, &mut nodes);
+                nodes.into_iter().for_each(|n| apply(&step.selector, n, &mut next));
+            } else {
+                apply(&step.selector, node, &mut next)
+            }
+        }
+        current = next;
+    }
+    current
+}
+
+fn descendants<'v>(v: &'v Value, out: &mut Vec<&'v Value>) {
+    out.push(v);
+    match v {
+        Value::Object(m) => m.values().for_each(|c| descendants(c, out)),
+        Value::Array(a) => a.iter().for_each(|c| descendants(c, out)),
+        _ => {}
+    }
+}
+
+fn apply<'v>(selector: &Selector, v: &'v Value, out: &mut Vec<&'v Value>) {
+    match (selector, v) {
+        (Selector::Key(k), Value::Object(m)) => {
+            if let Some(c) = m.get(k) {
+                out.push(c)
+            }
+        }
+        (Selector::Index(i), Value::Array(a)) => {
+            let i = if *i < 0 { a.len() as i64 + *i } else { *i };
+            if i >= 0 {
+                if let Some(c) = a.get(i as usize) {
+                    out.push(c)
+                }
+            }
+        }
+        (Selector::Wildcard, Value::Object(m)) => out.extend(m.values()),
+        (Selector::Wildcard, Value::Array(a)) => out.extend(a.iter()),
+        _ => {}
+    }
+}
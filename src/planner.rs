@@ -0,0 +1,324 @@
+//! Boolean queries answered from whichever indexes fit them best.
+//!
+//! Each condition is estimated against the trees of the available indexes. Among the conditions of an `And` the one
+//! with the fewest estimated document keys drives the query, other indexed conditions that are selective enough are
+//! intersected with it and the remaining conditions filter its documents. The documents are only scanned in full for
+//! conditions no index can answer.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use indexmap::map::IndexMap;
+use crate::{Index, Indexer, IndexJson, KeyRange, Op, QueryResult, LIKE_OPTIONS, key_range, path, wildcard_prefix};
+use crate::path::PathExpr;
+
+/// An indexed condition is intersected with the driving one when it matches at most this many times more keys
+const INTERSECT_RATIO: usize = 4;
+
+/// Buckets a range condition reads to estimate its matches, the rest of the tree is extrapolated from their sizes
+const ESTIMATE_BUCKETS: usize = 1_000;
+
+/// A boolean query over document fields. fields take the same dot paths, JSON Pointers and JSONPaths as `find_where`
+/// and conditions match like `find_where` does, array elements are matched one by one
+/// ## Example
+/// ```rust
+/// use indexer::{Query, Op};
+/// let q = Query::and(vec![
+///     Query::cond("state", Op::EQ, "CA"),
+///     Query::or(vec![Query::cond("age", Op::LT, 13), Query::cond("gpa", Op::GT, 3.5)]),
+///     !Query::cond("name", Op::LIKE, "Kw*"),
+/// ]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Query {
+    Cond(String, Op, Value),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    pub fn cond<V>(field: &str, op: Op, value: V) -> Self where V: Serialize {
+        Query::Cond(path::canonical(field), op, serde_json::to_value(value).unwrap_or(Value::Null))
+    }
+
+    pub fn and(queries: Vec<Query>) -> Self {
+        Query::And(queries)
+    }
+
+    pub fn or(queries: Vec<Query>) -> Self {
+        Query::Or(queries)
+    }
+
+    /// True when the document matches the query
+    pub fn matches(&self, v: &Value) -> bool {
+        match self {
+            Query::Cond(field, op, value) => {
+                let range = key_range(&scan_indexer(), value, op);
+                field_values(field, v).iter().any(|v| contains(&range, v))
+            }
+            Query::And(queries) => queries.iter().all(|q| q.matches(v)),
+            Query::Or(queries) => queries.iter().any(|q| q.matches(v)),
+            Query::Not(q) => !q.matches(v),
+        }
+    }
+}
+
+impl std::ops::Not for Query {
+    type Output = Query;
+
+    fn not(self) -> Query {
+        Query::Not(Box::new(self))
+    }
+}
+
+/// How a query is answered. `estimate` is the number of document keys a step is expected to produce
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Plan {
+    /// looks a condition up in the tree of an index
+    IndexScan { index: String, field: String, op: Op, value: Value, estimate: usize },
+    /// keys produced by every plan
+    Intersect { plans: Vec<Plan>, estimate: usize },
+    /// keys produced by any plan
+    Union { plans: Vec<Plan>, estimate: usize },
+    /// every document key except the ones of the plan
+    Complement { plan: Box<Plan>, estimate: usize },
+    /// keeps the documents of the plan matching a predicate, for conditions no index answers or not worth intersecting
+    Filter { plan: Box<Plan>, predicate: Query, estimate: usize },
+    /// checks every document against the predicate
+    FullScan { predicate: Query, estimate: usize },
+}
+
+impl Plan {
+    pub fn estimate(&self) -> usize {
+        match self {
+            Plan::IndexScan { estimate, .. } | Plan::Intersect { estimate, .. } | Plan::Union { estimate, .. }
+            | Plan::Complement { estimate, .. } | Plan::Filter { estimate, .. } | Plan::FullScan { estimate, .. } => *estimate
+        }
+    }
+
+    /// True when the plan reads the documents it returns from a full scan
+    pub fn is_full_scan(&self) -> bool {
+        matches!(self, Plan::FullScan { .. })
+    }
+}
+
+/// Plans and runs queries over documents and the indexes available on them
+pub(crate) struct Planner<'i> {
+    pub(crate) items: &'i Arc<RwLock<IndexMap<String, Value>>>,
+    pub(crate) indexes: Vec<(&'i str, &'i Index)>,
+}
+
+impl<'i> Planner<'i> {
+    pub(crate) fn plan(&self, q: &Query) -> Plan {
+        let size = self.items.read().unwrap().len();
+        self.plan_query(q, size)
+    }
+
+    pub(crate) fn find(&self, q: &Query) -> QueryResult {
+        let plan = self.plan(q);
+        let keys = self.run(&plan);
+        let items = self.items.read().unwrap();
+        // in the order of the documents, the key sets of the plan have none
+        let mut positions: Vec<usize> = keys.iter().filter_map(|k| items.get_index_of(k)).collect();
+        positions.sort_unstable();
+        let matches = positions.into_iter().filter_map(|i| items.get_index(i)).map(|(k, v)| (k.to_string(), v.clone())).collect();
+        QueryResult::new(matches, scan_indexer())
+    }
+
+    fn plan_query(&self, q: &Query, size: usize) -> Plan {
+        match q {
+            Query::Cond(field, op, value) => {
+                let field = path::canonical(field);
+                let best = self.indexes.iter()
+                    .filter(|(_, index)| covers(index, &field))
+                    .map(|(name, index)| (name, estimate(index, &field, op, value, size)))
+                    .min_by_key(|(_, estimate)| *estimate);
+                match best {
+                    Some((name, estimate)) => Plan::IndexScan { index: name.to_string(), field, op: *op, value: value.clone(), estimate },
+                    None => Plan::FullScan { predicate: q.clone(), estimate: size }
+                }
+            }
+            Query::And(queries) => {
+                let mut indexed = vec![];
+                let mut residual = vec![];
+                queries.iter().for_each(|q| {
+                    let plan = self.plan_query(q, size);
+                    if plan.is_full_scan() {
+                        residual.push(q.clone())
+                    } else {
+                        indexed.push(plan)
+                    }
+                });
+                if indexed.is_empty() {
+                    return Plan::FullScan { predicate: q.clone(), estimate: size };
+                }
+                indexed.sort_by_key(|p| p.estimate());
+                let driver = indexed[0].estimate();
+                let mut plans = vec![];
+                indexed.into_iter().for_each(|plan| {
+                    if plans.is_empty() || plan.estimate() <= driver.saturating_mul(INTERSECT_RATIO) {
+                        plans.push(plan)
+                    } else {
+                        residual.push(plan_query_of(&plan))
+                    }
+                });
+                let plan = if plans.len() == 1 {
+                    plans.remove(0)
+                } else {
+                    Plan::Intersect { plans, estimate: driver }
+                };
+                match residual.len() {
+                    0 => plan,
+                    1 => Plan::Filter { plan: Box::new(plan), predicate: residual.remove(0), estimate: driver },
+                    _ => Plan::Filter { plan: Box::new(plan), predicate: Query::And(residual), estimate: driver }
+                }
+            }
+            Query::Or(queries) => {
+                let plans: Vec<Plan> = queries.iter().map(|q| self.plan_query(q, size)).collect();
+                if plans.is_empty() || plans.iter().any(|p| p.is_full_scan()) {
+                    return Plan::FullScan { predicate: q.clone(), estimate: size };
+                }
+                let estimate = plans.iter().map(|p| p.estimate()).sum::<usize>().min(size);
+                Plan::Union { plans, estimate }
+            }
+            Query::Not(inner) => {
+                let plan = self.plan_query(inner, size);
+                if plan.is_full_scan() {
+                    return Plan::FullScan { predicate: q.clone(), estimate: size };
+                }
+                let estimate = size.saturating_sub(plan.estimate());
+                Plan::Complement { plan: Box::new(plan), estimate }
+            }
+        }
+    }
+
+    /// Returns the document keys a plan produces
+    fn run(&self, plan: &Plan) -> HashSet<String> {
+        match plan {
+            Plan::IndexScan { index, field, op, value, .. } => {
+                let mut keys = HashSet::new();
+                if let Some((_, index)) = self.indexes.iter().find(|(name, _)| name == index) {
+                    index.scan(field, value, op, false, None, &mut |_, bucket| {
+                        keys.extend(bucket.iter().cloned());
+                        true
+                    });
+                }
+                keys
+            }
+            Plan::Intersect { plans, .. } => {
+                let mut sets: Vec<HashSet<String>> = plans.iter().map(|p| self.run(p)).collect();
+                sets.sort_by_key(|s| s.len());
+                let mut sets = sets.into_iter();
+                let first = sets.next().unwrap_or_default();
+                sets.fold(first, |acc, s| acc.into_iter().filter(|k| s.contains(k)).collect())
+            }
+            Plan::Union { plans, .. } => {
+                plans.iter().flat_map(|p| self.run(p)).collect()
+            }
+            Plan::Complement { plan, .. } => {
+                let excluded = self.run(plan);
+                let items = self.items.read().unwrap();
+                items.keys().filter(|k| !excluded.contains(*k)).cloned().collect()
+            }
+            Plan::Filter { plan, predicate, .. } => {
+                let keys = self.run(plan);
+                let items = self.items.read().unwrap();
+                keys.into_iter().filter(|k| items.get(k).is_some_and(|v| predicate.matches(v))).collect()
+            }
+            Plan::FullScan { predicate, .. } => {
+                let items = self.items.read().unwrap();
+                items.iter().filter(|(_, v)| predicate.matches(v)).map(|(k, _)| k.to_string()).collect()
+            }
+        }
+    }
+}
+
+/// Returns the condition an index scan answers, to filter by it instead
+fn plan_query_of(plan: &Plan) -> Query {
+    match plan {
+        Plan::IndexScan { field, op, value, .. } => Query::Cond(field.to_string(), *op, value.clone()),
+        Plan::Intersect { plans, .. } => Query::And(plans.iter().map(plan_query_of).collect()),
+        Plan::Union { plans, .. } => Query::Or(plans.iter().map(plan_query_of).collect()),
+        Plan::Complement { plan, .. } => Query::Not(Box::new(plan_query_of(plan))),
+        Plan::Filter { plan, predicate, .. } => Query::And(vec![plan_query_of(plan), predicate.clone()]),
+        Plan::FullScan { predicate, .. } => predicate.clone(),
+    }
+}
+
+/// The indexer conditions are evaluated with when scanning, it picks the tree from the type of the value like json indexers do
+fn scan_indexer() -> Indexer {
+    Indexer::Json(IndexJson { path_orders: vec![] })
+}
+
+/// True when an index holds every document that can match a condition on `field`. json indexes only hold the
+/// documents having all their paths, so they answer a field only when it is their sole required path or it is
+/// under one of their wildcard paths
+pub(crate) fn covers(index: &Index, field: &str) -> bool {
+    match &index.indexer {
+        Indexer::Json(j) => {
+            let required: Vec<String> = j.path_orders.iter().filter(|p| !p.is_wildcard()).map(|p| path::canonical(&p.path)).collect();
+            if !required.iter().all(|r| r == field) {
+                return false;
+            }
+            !required.is_empty() || j.path_orders.iter().any(|p| match wildcard_prefix(&p.path) {
+                Some("") => true,
+                Some(prefix) => field.starts_with(&format!("{}.", prefix)),
+                None => false
+            })
+        }
+        _ => field == "*"
+    }
+}
+
+/// Estimates the document keys an index matches for a condition, at most `cap`. an EQ reads its one bucket, a range
+/// reads at most `ESTIMATE_BUCKETS` buckets and takes their average size for the tree keys it did not read
+fn estimate(index: &Index, field: &str, op: &Op, value: &Value, cap: usize) -> usize {
+    let (mut buckets, mut count) = (0, 0);
+    index.scan(field, value, op, false, None, &mut |_, bucket| {
+        buckets += 1;
+        count += bucket.len();
+        buckets < ESTIMATE_BUCKETS && count <= cap
+    });
+    if buckets < ESTIMATE_BUCKETS || count > cap {
+        return count.min(cap);
+    }
+    let unread = index.tree_keys(field, value, op).saturating_sub(buckets);
+    count.saturating_add(count / buckets * unread).min(cap)
+}
+
+/// Returns the scalar values at `field`, arrays are flattened like wildcard indexes do
+pub(crate) fn field_values(field: &str, v: &Value) -> Vec<Value> {
+    let values = if field == "*" { vec![v.clone()] } else { PathExpr::parse(field).resolve(v) };
+    let mut out = vec![];
+    values.into_iter().for_each(|v| flatten(v, &mut out));
+    out
+}
+
+fn flatten(v: Value, out: &mut Vec<Value>) {
+    match v {
+        Value::Array(a) => a.into_iter().for_each(|v| flatten(v, out)),
+        v => out.push(v)
+    }
+}
+
+/// True when a tree built like `insert_entry` does would list `v` within the range
+fn contains(range: &KeyRange, v: &Value) -> bool {
+    use std::ops::RangeBounds;
+    match range {
+        KeyRange::Int(lower, upper) => v.is_i64() && (lower.as_ref(), upper.as_ref()).contains(&v.as_i64().unwrap()),
+        KeyRange::Float(lower, upper) => v.is_f64() && (lower.as_ref(), upper.as_ref()).contains(&crate::FloatKey(v.as_f64().unwrap())),
+        KeyRange::Str(lower, upper, glob_matcher) => match v.as_str() {
+            Some(s) => {
+                (lower.as_ref(), upper.as_ref()).contains(&s.to_string()) && match glob_matcher {
+                    Some(m) => m.matches_with(s, LIKE_OPTIONS),
+                    None => true
+                }
+            }
+            None => false
+        },
+        KeyRange::All => true,
+        KeyRange::Empty => false,
+    }
+}
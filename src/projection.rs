@@ -0,0 +1,116 @@
+//! Projections of the documents returned by a query.
+
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use crate::path::PathExpr;
+
+/// Selects the parts of the documents a query returns. paths are dot paths, JSON Pointers or JSONPaths addressing
+/// one location through object keys, other paths select nothing
+/// ## Example
+/// ```rust
+/// use indexer::Projection;
+/// use serde_json::json;
+/// let projection = Projection::new().include("name").include("address.city").rename("gpa", "grade");
+/// let doc = json!({"name": "Kwadwo", "age": 21, "gpa": 3.5, "address": {"city": "Accra", "zip": "00233"}});
+/// assert_eq!(projection.apply(&doc), json!({"name": "Kwadwo", "address": {"city": "Accra"}, "grade": 3.5}));
+/// assert_eq!(Projection::new().exclude("address").apply(&doc), json!({"name": "Kwadwo", "age": 21, "gpa": 3.5}));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Projection {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    rename: Vec<(String, String)>,
+}
+
+impl Projection {
+    /// A projection keeping whole documents
+    pub fn new() -> Self {
+        Projection::default()
+    }
+
+    /// Keeps a path. once a path is included, only the included and renamed paths are kept
+    pub fn include(mut self, path: &str) -> Self {
+        self.include.push(path.to_string());
+        self
+    }
+
+    /// Removes a path from the projected documents, applied after includes and renames
+    pub fn exclude(mut self, path: &str) -> Self {
+        self.exclude.push(path.to_string());
+        self
+    }
+
+    /// Moves the value at `from` to `to`, a renamed path counts as included
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.rename.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Projects a document, only the selected values are cloned when paths are included
+    pub fn apply(&self, v: &Value) -> Value {
+        let mut out = if self.include.is_empty() && self.rename.is_empty() {
+            v.clone()
+        } else if self.include.is_empty() {
+            let mut out = v.clone();
+            self.rename.iter().for_each(|(from, _)| {
+                remove(&mut out, &keys(from));
+            });
+            out
+        } else {
+            let mut out = Value::Object(Map::new());
+            self.include.iter().for_each(|path| {
+                let keys = keys(path);
+                if let Some(value) = get(v, &keys) {
+                    set(&mut out, &keys, value.clone())
+                }
+            });
+            out
+        };
+        self.rename.iter().for_each(|(from, to)| {
+            if let Some(value) = get(v, &keys(from)) {
+                set(&mut out, &keys(to), value.clone())
+            }
+        });
+        self.exclude.iter().for_each(|path| {
+            remove(&mut out, &keys(path));
+        });
+        out
+    }
+}
+
+fn keys(path: &str) -> Vec<String> {
+    PathExpr::parse(path).keys().unwrap_or_default()
+}
+
+fn get<'v>(v: &'v Value, keys: &[String]) -> Option<&'v Value> {
+    if keys.is_empty() {
+        return None;
+    }
+    keys.iter().try_fold(v, |current, k| current.as_object().and_then(|m| m.get(k)))
+}
+
+fn set(v: &mut Value, keys: &[String], value: Value) {
+    let (last, parents) = match keys.split_last() {
+        Some(split) => split,
+        None => return
+    };
+    let mut current = v;
+    for k in parents {
+        if !current.is_object() {
+            return;
+        }
+        current = current.as_object_mut().unwrap().entry(k.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+    if let Some(m) = current.as_object_mut() {
+        m.insert(last.to_string(), value);
+    }
+}
+
+fn remove(v: &mut Value, keys: &[String]) -> Option<Value> {
+    let (last, parents) = keys.split_last()?;
+    let mut current = v;
+    for k in parents {
+        current = current.as_object_mut()?.get_mut(k)?;
+    }
+    current.as_object_mut()?.remove(last)
+}
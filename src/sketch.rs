@@ -0,0 +1,285 @@
+//! Approximate statistics of a path: a HyperLogLog of its distinct keys and a t-digest of its numeric values.
+//!
+//! Both are updated as entries are added to the trees. Neither can forget a value, so removing an entry only marks
+//! the sketch dirty and it is rebuilt from the trees of the path the next time it is read.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::{FloatKey, MultiMap};
+
+/// Registers of a HyperLogLog are addressed by this many bits of the hash, 2^14 registers give a standard error
+/// of about 0.8%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// t-digest compression, the digest keeps at most about this many centroids
+const COMPRESSION: f64 = 100.0;
+/// Values added to a t-digest are buffered and merged into its centroids once there are this many
+const BUFFER: usize = 500;
+
+/// Approximate statistics of a path, returned by `Index::sketch`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sketch {
+    distinct: HyperLogLog,
+    quantiles: TDigest,
+    dirty: bool,
+}
+
+impl Sketch {
+    pub(crate) fn new() -> Self {
+        Sketch { distinct: HyperLogLog::new(), quantiles: TDigest::new(), dirty: false }
+    }
+
+    /// Estimated number of distinct keys
+    pub fn distinct_count(&self) -> u64 {
+        self.distinct.count()
+    }
+
+    /// Estimated quantile of the numeric values, `q` goes from 0 to 1
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        self.quantiles.quantile(q)
+    }
+
+    pub fn distinct(&self) -> &HyperLogLog {
+        &self.distinct
+    }
+
+    pub fn quantiles(&self) -> &TDigest {
+        &self.quantiles
+    }
+
+    /// Adds the statistics of another sketch, e.g. of the same path in another index. keys present in both count once
+    pub fn merge(&mut self, other: &Sketch) {
+        self.distinct.merge(&other.distinct);
+        self.quantiles.merge(&other.quantiles);
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub(crate) fn mark_dirty(&mut self) {
+        self.dirty = true
+    }
+
+    pub(crate) fn add(&mut self, v: &Value) {
+        self.add_key(v, 1)
+    }
+
+    /// Rebuilds the sketch from the trees of its path
+    pub(crate) fn rebuild(ints: Option<&MultiMap<i64, String>>, floats: Option<&MultiMap<FloatKey, String>>, strs: Option<&MultiMap<String, String>>) -> Self {
+        let mut sketch = Sketch::new();
+        ints.into_iter().flatten().for_each(|(k, bucket)| sketch.add_key(&Value::from(*k), bucket.len()));
+        floats.into_iter().flatten().for_each(|(k, bucket)| sketch.add_key(&Value::from(k.0), bucket.len()));
+        strs.into_iter().flatten().for_each(|(k, bucket)| sketch.add_key(&Value::from(k.as_str()), bucket.len()));
+        sketch.quantiles.compress();
+        sketch
+    }
+
+    fn add_key(&mut self, v: &Value, entries: usize) {
+        if let Some(hash) = hash_value(v) {
+            self.distinct.add_hash(hash);
+        }
+        if let Some(f) = v.as_f64() {
+            self.quantiles.add_weighted(f, entries as f64);
+        }
+    }
+}
+
+/// Hashes the keys a tree can hold, the type is part of the hash so `1`, `1.0` and `"1"` are distinct
+fn hash_value(v: &Value) -> Option<u64> {
+    let hash = match v {
+        Value::Number(n) if n.is_i64() => fnv1a(0, &n.as_i64().unwrap().to_le_bytes()),
+        Value::Number(n) if n.is_f64() => fnv1a(1, &n.as_f64().unwrap().to_bits().to_le_bytes()),
+        Value::String(s) => fnv1a(2, s.as_bytes()),
+        _ => return None
+    };
+    Some(splitmix64(hash))
+}
+
+fn fnv1a(tag: u8, bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    std::iter::once(&tag).chain(bytes).for_each(|b| {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    });
+    hash
+}
+
+/// Finalizer of splitmix64, spreads the low bits FNV mixes poorly over the whole hash
+fn splitmix64(hash: u64) -> u64 {
+    let mut z = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Estimates the number of distinct hashes added
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+
+    /// Adds a 64-bit hash, the hashes must be uniformly distributed
+    pub fn add_hash(&mut self, hash: u64) {
+        let register = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if self.registers[register] < rank {
+            self.registers[register] = rank
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        self.registers.iter_mut().zip(other.registers.iter()).for_each(|(r, o)| *r = (*r).max(*o))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// Estimates quantiles of the values added. centroids near the median absorb more values than those at the tails,
+/// so extreme quantiles stay precise
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new() -> Self {
+        TDigest::default()
+    }
+
+    pub fn add(&mut self, v: f64) {
+        self.add_weighted(v, 1.0)
+    }
+
+    /// Number of values added
+    pub fn count(&self) -> f64 {
+        self.count
+    }
+
+    pub fn merge(&mut self, other: &TDigest) {
+        if other.count == 0.0 {
+            return;
+        }
+        self.update_bounds(other.min, other.max);
+        self.count += other.count;
+        self.buffer.extend(other.centroids.iter().chain(other.buffer.iter()));
+        self.compress();
+    }
+
+    /// Estimated quantile, `None` when no value was added or `q` is outside 0..=1
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0.0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        if !self.buffer.is_empty() {
+            let mut digest = self.clone();
+            digest.compress();
+            return digest.quantile(q);
+        }
+        let target = q * self.count;
+        let mut seen = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for c in self.centroids.iter() {
+            let center = seen + c.weight / 2.0;
+            if target < center {
+                let (mean, at) = previous.unwrap_or((self.min, 0.0));
+                return Some(interpolate(mean, at, c.mean, center, target));
+            }
+            previous = Some((c.mean, center));
+            seen += c.weight;
+        }
+        let (mean, at) = previous.unwrap_or((self.min, 0.0));
+        Some(interpolate(mean, at, self.max, self.count, target))
+    }
+
+    fn add_weighted(&mut self, v: f64, weight: f64) {
+        if v.is_nan() || weight <= 0.0 {
+            return;
+        }
+        self.update_bounds(v, v);
+        self.count += weight;
+        self.buffer.push(Centroid { mean: v, weight });
+        if self.buffer.len() >= BUFFER {
+            self.compress()
+        }
+    }
+
+    fn update_bounds(&mut self, min: f64, max: f64) {
+        if self.count == 0.0 {
+            self.min = min;
+            self.max = max;
+        } else {
+            self.min = self.min.min(min);
+            self.max = self.max.max(max);
+        }
+    }
+
+    /// Merges the buffered values into the centroids. neighbouring centroids are merged while the merged one stays
+    /// within one unit of the scale function `k(q) = compression / 2π * asin(2q - 1)`
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all: Vec<Centroid> = self.centroids.drain(..).chain(self.buffer.drain(..)).collect();
+        all.sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap_or(std::cmp::Ordering::Equal));
+        let total: f64 = all.iter().map(|c| c.weight).sum();
+        let k = |q: f64| COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin();
+        let mut merged: Vec<Centroid> = vec![];
+        let mut before = 0.0;
+        let mut current = all[0];
+        all.into_iter().skip(1).for_each(|c| {
+            let weight = current.weight + c.weight;
+            if k((before + weight) / total) - k(before / total) <= 1.0 {
+                current.mean += (c.mean - current.mean) * c.weight / weight;
+                current.weight = weight;
+            } else {
+                before += current.weight;
+                merged.push(current);
+                current = c;
+            }
+        });
+        merged.push(current);
+        self.centroids = merged;
+    }
+}
+
+fn interpolate(x0: f64, at0: f64, x1: f64, at1: f64, target: f64) -> f64 {
+    if at1 <= at0 {
+        return x1;
+    }
+    x0 + (x1 - x0) * (target - at0) / (at1 - at0)
+}
+
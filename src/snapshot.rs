@@ -0,0 +1,284 @@
+//! Binary snapshot format of an [`Index`](crate::Index).
+//!
+//! Only the indexer definition, the sketches and the items are persisted, the trees are rebuilt on load.
+//! The snapshot streams one record per document, so neither writing nor reading needs the whole snapshot in memory,
+//! and every record carries its own checksum so a damaged snapshot can still be loaded up to the last valid record.
+//!
+//! ```text
+//! magic    4 bytes  "JIDX"
+//! version  u8       1
+//! records  [u32 LE length][u32 LE crc32][CBOR payload], the indexer, the sketches by path, then one
+//!          [key, document] per item
+//! end      u32 LE   0
+//! count    u64 LE   number of documents
+//! checksum u32 LE   crc32 of the records
+//! ```
+
+use indexmap::map::IndexMap;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use crate::{Indexer, PersistError, RecoveryReport, Sketch};
+
+pub(crate) const MAGIC: &[u8; 4] = b"JIDX";
+pub(crate) const VERSION: u8 = 1;
+
+/// The persisted part of an index
+#[derive(Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) indexer: Indexer,
+    #[serde(default)]
+    pub(crate) sketches: HashMap<String, Sketch>,
+    pub(crate) items: IndexMap<String, Value>,
+}
+
+pub(crate) fn is_snapshot(b: &[u8]) -> bool {
+    b.starts_with(MAGIC)
+}
+
+/// Writes length prefixed, checksummed CBOR records and keeps a running checksum of them
+pub(crate) struct RecordWriter<W: Write> {
+    w: W,
+    hasher: crc32fast::Hasher,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub(crate) fn new(w: W) -> Self {
+        RecordWriter {
+            w,
+            hasher: crc32fast::Hasher::new(),
+            buf: vec![],
+        }
+    }
+
+    pub(crate) fn write<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        self.buf.clear();
+        ciborium::ser::into_writer(record, &mut self.buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let length = (self.buf.len() as u32).to_le_bytes();
+        let checksum = crc32fast::hash(&self.buf).to_le_bytes();
+        self.hasher.update(&length);
+        self.hasher.update(&checksum);
+        self.hasher.update(&self.buf);
+        self.w.write_all(&length)?;
+        self.w.write_all(&checksum)?;
+        self.w.write_all(&self.buf)
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    pub(crate) fn into_inner(self) -> (W, u32) {
+        (self.w, self.hasher.finalize())
+    }
+}
+
+/// Reads records written by [`RecordWriter`]
+pub(crate) struct RecordReader<R: Read> {
+    r: R,
+    hasher: crc32fast::Hasher,
+    buf: Vec<u8>,
+    records: usize,
+    consumed: u64,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub(crate) fn new(r: R) -> Self {
+        RecordReader {
+            r,
+            hasher: crc32fast::Hasher::new(),
+            buf: vec![],
+            records: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Returns the next record, `None` at the end marker
+    pub(crate) fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, PersistError> {
+        match self.read_length()? {
+            Some(0) => Ok(None),
+            Some(len) => self.read_payload(len).map(Some),
+            None => Err(PersistError::Truncated { record: self.records })
+        }
+    }
+
+    /// Returns the next record, `None` when the input ends cleanly between two records
+    pub(crate) fn read_until_eof<T: DeserializeOwned>(&mut self) -> Result<Option<T>, PersistError> {
+        match self.read_length()? {
+            Some(0) => Err(PersistError::Corrupt { record: self.records }),
+            Some(len) => self.read_payload(len).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    /// Counts the records left before the end of the input or the end marker without decoding them, a record cut
+    /// short counts as one
+    pub(crate) fn skip_remaining(&mut self) -> usize {
+        let mut count = 0;
+        loop {
+            let len = match self.read_length() {
+                Ok(Some(0)) | Ok(None) => break,
+                Ok(Some(len)) => len,
+                Err(_) => return count + 1
+            };
+            let skip = len as u64 + 4;
+            match io::copy(&mut (&mut self.r).take(skip), &mut io::sink()) {
+                Ok(n) if n == skip => count += 1,
+                _ => return count + 1
+            }
+        }
+        count
+    }
+
+    /// Number of records read so far
+    pub(crate) fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Number of bytes taken by the records read so far
+    pub(crate) fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Reads a record length, `None` when the input ends before it
+    fn read_length(&mut self) -> Result<Option<usize>, PersistError> {
+        let mut length = [0u8; 4];
+        let mut filled = 0;
+        while filled < length.len() {
+            match self.r.read(&mut length[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(PersistError::Truncated { record: self.records }),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into())
+            }
+        }
+        Ok(Some(u32::from_le_bytes(length) as usize))
+    }
+
+    fn read_payload<T: DeserializeOwned>(&mut self, len: usize) -> Result<T, PersistError> {
+        let record = self.records;
+        let mut checksum = [0u8; 4];
+        self.read_exact_at(&mut checksum, record)?;
+        // read through `take` so a damaged length does not allocate more than the input holds
+        self.buf.clear();
+        (&mut self.r).take(len as u64).read_to_end(&mut self.buf)?;
+        if self.buf.len() < len {
+            return Err(PersistError::Truncated { record });
+        }
+        if crc32fast::hash(&self.buf) != u32::from_le_bytes(checksum) {
+            return Err(PersistError::Corrupt { record });
+        }
+        self.hasher.update(&(len as u32).to_le_bytes());
+        self.hasher.update(&checksum);
+        self.hasher.update(&self.buf);
+        let value = ciborium::de::from_reader(self.buf.as_slice()).map_err(|e| PersistError::Decode { record, message: e.to_string() })?;
+        self.records += 1;
+        self.consumed += 8 + len as u64;
+        Ok(value)
+    }
+
+    fn read_exact_at(&mut self, b: &mut [u8], record: usize) -> Result<(), PersistError> {
+        self.r.read_exact(b).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => PersistError::Truncated { record },
+            _ => e.into()
+        })
+    }
+
+    pub(crate) fn into_inner(self) -> (R, u32) {
+        (self.r, self.hasher.finalize())
+    }
+}
+
+pub(crate) fn write_stream<W: Write>(w: W, indexer: &Indexer, sketches: &HashMap<String, Sketch>, items: &IndexMap<String, Value>) -> io::Result<W> {
+    let mut w = w;
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    let mut records = RecordWriter::new(w);
+    records.write(indexer)?;
+    records.write(sketches)?;
+    for (k, v) in items.iter() {
+        records.write(&(k, v))?;
+    }
+    let (mut w, checksum) = records.into_inner();
+    w.write_all(&0u32.to_le_bytes())?;
+    w.write_all(&(items.len() as u64).to_le_bytes())?;
+    w.write_all(&checksum.to_le_bytes())?;
+    Ok(w)
+}
+
+pub(crate) fn read_stream<R: Read>(r: R) -> Result<Snapshot, PersistError> {
+    read(r, false).map(|(s, _)| s)
+}
+
+/// Reads a snapshot keeping the documents before the first damaged record, the indexer record must be intact
+pub(crate) fn read_partial<R: Read>(r: R) -> Result<(Snapshot, RecoveryReport), PersistError> {
+    read(r, true)
+}
+
+fn read<R: Read>(r: R, partial: bool) -> Result<(Snapshot, RecoveryReport), PersistError> {
+    let mut r = r;
+    let mut header = [0u8; 5];
+    r.read_exact(&mut header).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => PersistError::NotAnIndex,
+        _ => e.into()
+    })?;
+    if !is_snapshot(&header) {
+        return Err(PersistError::NotAnIndex);
+    }
+    if header[4] != VERSION {
+        return Err(PersistError::UnsupportedVersion(header[4]));
+    }
+    let mut records = RecordReader::new(r);
+    let indexer: Indexer = records.read()?.ok_or(PersistError::Truncated { record: 0 })?;
+    let sketches = records.read()?.ok_or(PersistError::Truncated { record: 1 })?;
+    let mut items = IndexMap::new();
+    loop {
+        match records.read::<(String, Value)>() {
+            Ok(Some((k, v))) => {
+                items.insert(k, v);
+            }
+            Ok(None) => break,
+            Err(e) if partial && e.is_damage() => {
+                let dropped = match e {
+                    PersistError::Truncated { .. } => 1,
+                    _ => 1 + records.skip_remaining()
+                };
+                let report = RecoveryReport { recovered: items.len(), dropped };
+                return Ok((Snapshot { indexer, sketches, items }, report));
+            }
+            Err(e) => return Err(e)
+        }
+    }
+    let (mut r, checksum) = records.into_inner();
+    let mut trailer = [0u8; 12];
+    let trailer = match r.read_exact(&mut trailer) {
+        Ok(_) => Some(trailer),
+        Err(e) if partial && e.kind() == io::ErrorKind::UnexpectedEof => None,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(PersistError::Truncated { record: items.len() + 1 }),
+        Err(e) => return Err(e.into())
+    };
+    let mut report = RecoveryReport { recovered: items.len(), dropped: 0 };
+    if let Some(trailer) = trailer {
+        let mut count = [0u8; 8];
+        count.copy_from_slice(&trailer[..8]);
+        let mut expected = [0u8; 4];
+        expected.copy_from_slice(&trailer[8..]);
+        let count = u64::from_le_bytes(count);
+        if count != items.len() as u64 || u32::from_le_bytes(expected) != checksum {
+            if !partial {
+                return Err(PersistError::ChecksumMismatch);
+            }
+            report.dropped = (count as usize).saturating_sub(items.len());
+        }
+    }
+    Ok((Snapshot { indexer, sketches, items }, report))
+}
+
+/// Reads a snapshot written as JSON by the versions before the binary format
+pub(crate) fn decode_legacy(b: &[u8]) -> Result<Snapshot, PersistError> {
+    serde_json::from_slice(b).map_err(|e| PersistError::Decode { record: 0, message: e.to_string() })
+}
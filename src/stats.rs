@@ -0,0 +1,137 @@
+//! Per-path statistics of the trees of an index.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::mem::size_of;
+use crate::{FloatKey, Index};
+
+/// Rough per-entry cost of a `BTreeMap` node slot and of a `HashSet` slot beyond the key itself
+const BTREE_ENTRY_OVERHEAD: usize = 16;
+const HASH_ENTRY_OVERHEAD: usize = 8;
+
+/// Statistics of an index, returned by `Index::stats`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub documents: usize,
+    /// one entry per tree, sorted by path then tree
+    pub paths: Vec<PathStats>,
+    /// approximate heap size of the documents and their keys
+    pub items_memory_bytes: usize,
+    /// approximate heap size of the documents and every tree
+    pub memory_bytes: usize,
+}
+
+/// Statistics of the tree of one path. a path holding values of several types has one tree per type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathStats {
+    pub path: String,
+    /// `int_tree`, `float_tree` or `str_tree`
+    pub tree: String,
+    pub distinct_keys: usize,
+    /// documents with at least one key in the tree
+    pub documents: usize,
+    /// (key, document) pairs, larger than `documents` when documents hold several values at the path
+    pub entries: usize,
+    pub min_key: Option<Value>,
+    pub max_key: Option<Value>,
+    pub bucket_sizes: BucketSizes,
+    /// approximate heap size of the tree
+    pub memory_bytes: usize,
+}
+
+/// Distribution of the number of documents per key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BucketSizes {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    /// `histogram[i]` counts the buckets holding up to `2^i` documents and more than `2^(i-1)`
+    pub histogram: Vec<usize>,
+}
+
+impl BucketSizes {
+    fn new(sizes: impl Iterator<Item=usize>) -> Self {
+        let mut out = BucketSizes { min: 0, max: 0, mean: 0.0, histogram: vec![] };
+        let mut count = 0;
+        let mut total = 0;
+        sizes.for_each(|size| {
+            out.min = if count == 0 { size } else { out.min.min(size) };
+            out.max = out.max.max(size);
+            count += 1;
+            total += size;
+            let slot = size.max(1).next_power_of_two().trailing_zeros() as usize;
+            if out.histogram.len() <= slot {
+                out.histogram.resize(slot + 1, 0);
+            }
+            out.histogram[slot] += 1;
+        });
+        if count > 0 {
+            out.mean = total as f64 / count as f64;
+        }
+        out
+    }
+}
+
+pub(crate) fn collect(index: &Index) -> IndexStats {
+    let items = index.items.read().unwrap();
+    let int_tree = index.int_tree.read().unwrap();
+    let float_tree = index.float_tree.read().unwrap();
+    let str_tree = index.str_tree.read().unwrap();
+
+    let mut paths = vec![];
+    int_tree.iter().for_each(|(path, tree)| {
+        paths.push(path_stats(path, "int_tree", tree, |k| Value::from(*k), |_| 0))
+    });
+    float_tree.iter().for_each(|(path, tree)| {
+        paths.push(path_stats(path, "float_tree", tree, |k: &FloatKey| Value::from(k.0), |_| 0))
+    });
+    str_tree.iter().for_each(|(path, tree)| {
+        paths.push(path_stats(path, "str_tree", tree, |k: &String| Value::from(k.as_str()), |k: &String| k.capacity()))
+    });
+    paths.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.tree.cmp(&b.tree)));
+
+    let items_memory_bytes = items.iter().map(|(k, v)| size_of::<(String, Value)>() + k.capacity() + value_size(v)).sum();
+    let memory_bytes = items_memory_bytes + paths.iter().map(|p| p.memory_bytes).sum::<usize>();
+    IndexStats {
+        documents: items.len(),
+        paths,
+        items_memory_bytes,
+        memory_bytes,
+    }
+}
+
+fn path_stats<K>(path: &str, tree_name: &str, tree: &BTreeMap<K, HashSet<String>>, to_value: impl Fn(&K) -> Value, key_heap: impl Fn(&K) -> usize) -> PathStats {
+    let mut documents = HashSet::new();
+    let mut entries = 0;
+    let mut memory_bytes = path.len();
+    tree.iter().for_each(|(k, bucket)| {
+        entries += bucket.len();
+        memory_bytes += size_of::<K>() + key_heap(k) + size_of::<HashSet<String>>() + BTREE_ENTRY_OVERHEAD;
+        bucket.iter().for_each(|doc| {
+            memory_bytes += size_of::<String>() + doc.capacity() + HASH_ENTRY_OVERHEAD;
+            documents.insert(doc.as_str());
+        });
+    });
+    PathStats {
+        path: path.to_string(),
+        tree: tree_name.to_string(),
+        distinct_keys: tree.len(),
+        documents: documents.len(),
+        entries,
+        min_key: tree.keys().next().map(&to_value),
+        max_key: tree.keys().next_back().map(&to_value),
+        bucket_sizes: BucketSizes::new(tree.values().map(|b| b.len())),
+        memory_bytes,
+    }
+}
+
+/// Approximate heap size of a value
+fn value_size(v: &Value) -> usize {
+    match v {
+        Value::String(s) => s.capacity(),
+        Value::Array(a) => a.iter().map(|v| size_of::<Value>() + value_size(v)).sum(),
+        Value::Object(m) => m.iter().map(|(k, v)| size_of::<(String, Value)>() + k.capacity() + value_size(v) + HASH_ENTRY_OVERHEAD).sum(),
+        _ => 0
+    }
+}
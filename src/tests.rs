@@ -221,3 +221,1192 @@ fn load_json_from_with_incremental_inserts() {
         handle.join().unwrap();
     }
 }
+
+#[test]
+fn wildcard_path_indexing() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("name", IndexOrd::ASC), JsonPathOrder::new("attrs.*", IndexOrd::ASC)]
+    });
+
+    let mut products_index = Index::new(indexer);
+    products_index.insert("product:1", serde_json::json!({
+        "name": "shirt",
+        "attrs": {"color": "red", "size": {"width": 40}, "tags": ["cotton", "summer"]}
+    }));
+    products_index.insert("product:2", serde_json::json!({
+        "name": "hat",
+        "attrs": {"color": "blue", "weight": 0.5}
+    }));
+    products_index.insert("product:3", serde_json::json!({
+        "name": "scarf"
+    }));
+
+    assert_eq!(products_index.size(), 3);
+
+    let query = products_index.find_where("attrs.color", Op::EQ, "red");
+    assert_eq!(query.count(), 1);
+    assert_eq!(query.get()[0].0, "product:1");
+
+    let query = products_index.find_where("attrs.size.width", Op::GT, 30);
+    assert_eq!(query.count(), 1);
+
+    let query = products_index.find_where("attrs.tags", Op::EQ, "summer");
+    assert_eq!(query.count(), 1);
+
+    products_index.remove("product:2");
+    let query = products_index.find_where("attrs.weight", Op::LT, 1.0);
+    assert_eq!(query.count(), 0);
+
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("$**", IndexOrd::ASC)]
+    });
+    let mut all_index = Index::new(indexer);
+    all_index.insert("doc:1", serde_json::json!({"a": {"b": 1}, "c": "x"}));
+    assert_eq!(all_index.find_where("a.b", Op::EQ, 1).count(), 1);
+    assert_eq!(all_index.find_where("c", Op::EQ, "x").count(), 1);
+}
+
+#[test]
+fn json_pointer_and_json_path() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![
+            JsonPathOrder::new("/attrs/a.b", IndexOrd::ASC),
+            JsonPathOrder::new("$.items[*].sku", IndexOrd::ASC),
+            JsonPathOrder::new("$..id", IndexOrd::ASC),
+        ]
+    });
+
+    let mut orders_index = Index::new(indexer);
+    orders_index.insert("order:1", serde_json::json!({
+        "attrs": {"a.b": "x"},
+        "items": [{"sku": "A-1", "id": 10}, {"sku": "B-2", "id": 11}]
+    }));
+    orders_index.insert("order:2", serde_json::json!({
+        "attrs": {"a.b": "y"},
+        "items": [{"sku": "B-2", "id": 12}]
+    }));
+
+    assert_eq!(orders_index.find_where("/attrs/a.b", Op::EQ, "x").count(), 1);
+    assert_eq!(orders_index.find_where("$['attrs']['a.b']", Op::EQ, "y").count(), 1);
+    assert_eq!(orders_index.find_where("$.items[*].sku", Op::EQ, "B-2").count(), 2);
+    assert_eq!(orders_index.find_where("$..id", Op::GT, 10).count(), 2);
+
+    orders_index.remove("order:2");
+    assert_eq!(orders_index.find_where("$.items[*].sku", Op::EQ, "B-2").count(), 1);
+
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("$.address.city", IndexOrd::ASC)]
+    });
+    let mut users_index = Index::new(indexer);
+    users_index.insert("user:1", serde_json::json!({"address": {"city": "Accra"}}));
+    assert_eq!(users_index.find_where("address.city", Op::EQ, "Accra").count(), 1);
+    assert_eq!(users_index.find_where("/address/city", Op::EQ, "Accra").count(), 1);
+}
+
+#[test]
+fn skip_and_cursor_pagination() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)]
+    });
+    let age_order = indexer.clone();
+
+    let mut students_index = Index::new(indexer);
+    for i in 0..10 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: (i / 2) as u8 + 10,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        });
+    }
+
+    let mut query = students_index.find_where("age", Op::GT, 0);
+    let page: Vec<String> = query.order_by(age_order.clone()).skip(2).limit(3).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(page, vec!["student:2", "student:3", "student:4"]);
+
+    let mut query = students_index.find_where("age", Op::GT, 0);
+    let mut first_page = query.order_by(age_order.clone());
+    let cursor = first_page.limit(3).cursor().unwrap();
+    let cursor: Cursor = cursor.to_string().parse().unwrap();
+
+    students_index.insert("student:10", Student {
+        name: "Late".to_owned(),
+        age: 10,
+        state: "CA".to_owned(),
+        gpa: 3.0,
+    });
+    students_index.insert("student:21", Student {
+        name: "Later".to_owned(),
+        age: 11,
+        state: "CA".to_owned(),
+        gpa: 3.0,
+    });
+
+    let mut query = students_index.find_where("age", Op::GT, 0);
+    let page: Vec<String> = query.order_by(age_order).after(&cursor).limit(3).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(page, vec!["student:21", "student:3", "student:4"]);
+
+    assert!("zz".parse::<Cursor>().is_err());
+}
+
+#[test]
+fn ordered_limit_streams_from_tree() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC), JsonPathOrder::new("name", IndexOrd::ASC)]
+    });
+
+    let mut students_index = Index::new(indexer);
+    for i in 0..50 {
+        students_index.insert(&format!("student:{:02}", i), Student {
+            name: format!("Student {:02}", i),
+            age: (i % 20) as u8,
+            state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: i as f64 / 10.0,
+        });
+    }
+
+    let age_desc = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)]
+    });
+
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    assert_eq!(query.count(), 8);
+    let streamed: Vec<String> = query.order_by(age_desc.clone()).limit(3).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(streamed, vec!["student:19", "student:39", "student:18"]);
+
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    query.get();
+    let sorted: Vec<String> = query.order_by(age_desc.clone()).limit(3).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(sorted, streamed);
+
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    let mut page = query.order_by(age_desc.clone());
+    let page = page.skip(1).limit(2);
+    let cursor = page.cursor().unwrap();
+    assert_eq!(page.get().iter().map(|(k, _)| k.to_string()).collect::<Vec<String>>(), vec!["student:39", "student:18"]);
+
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    let next: Vec<String> = query.order_by(age_desc.clone()).after(&cursor).limit(2).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(next, vec!["student:38", "student:17"]);
+
+    // the order and a pending skip outlive the ordered result
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    {
+        query.order_by(age_desc.clone()).skip(7);
+    }
+    let rest: Vec<String> = query.get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(rest, vec!["student:36"]);
+    let mut query = students_index.find_where("age", Op::GT, 15);
+    {
+        query.order_by(age_desc);
+    }
+    let ages: Vec<u64> = query.get().iter().map(|(_, v)| v["age"].as_u64().unwrap()).collect();
+    assert_eq!(ages, vec![19, 19, 18, 18, 17, 17, 16, 16]);
+
+    let name_asc = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("name", IndexOrd::ASC)]
+    });
+    let mut query = students_index.find_where("name", Op::LIKE, "Student 4*");
+    let names: Vec<String> = query.order_by(name_asc).limit(20).get().iter().map(|(k, _)| k.to_string()).collect();
+    assert_eq!(names.len(), 10);
+    assert_eq!(names[0], "student:40");
+}
+
+#[test]
+fn iterate_matches_without_cloning() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC), JsonPathOrder::new("name", IndexOrd::ASC)]
+    });
+
+    let mut students_index = Index::new(indexer);
+    for i in 0..20 {
+        students_index.insert(&format!("student:{:02}", i), Student {
+            name: format!("Student {:02}", i),
+            age: i as u8,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        });
+    }
+
+    let matches = students_index.iter_where("age", Op::GT, 15);
+    let keys: Vec<&String> = matches.iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec!["student:16", "student:17", "student:18", "student:19"]);
+    let first = matches.iter_rev().next().unwrap();
+    assert_eq!(first.0, "student:19");
+    drop(matches);
+
+    assert_eq!(students_index.range("age", 5..8).iter().count(), 3);
+    assert_eq!(students_index.range("age", ..=2).iter().filter(|(_, v)| v["age"].as_i64() != Some(0)).count(), 2);
+    assert_eq!(students_index.range::<String>("name", "Student 10".to_string()..).iter().take(3).count(), 3);
+    assert_eq!(students_index.range::<i64>("age", ..).iter().count(), 20);
+    assert_eq!(students_index.iter_where("name", Op::LIKE, "Student 1*").iter().count(), 10);
+
+    students_index.remove("student:19");
+    assert_eq!(students_index.iter_where("age", Op::GT, 15).iter().count(), 3);
+}
+
+#[test]
+fn trees_hold_document_keys_only() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("name", IndexOrd::ASC), JsonPathOrder::new("state", IndexOrd::ASC), JsonPathOrder::new("gpa", IndexOrd::DESC)]
+    });
+
+    let mut students_index = Index::new(indexer);
+    students_index.insert("student:18", Student {
+        name: "Alex".to_owned(),
+        age: 15,
+        state: "NY".to_owned(),
+        gpa: 3.7,
+    });
+    students_index.insert("student:18", Student {
+        name: "Jackson".to_owned(),
+        age: 17,
+        state: "NY".to_owned(),
+        gpa: 3.8,
+    });
+
+    assert_eq!(students_index.find_where("name", Op::EQ, "Alex").count(), 0);
+    let query = students_index.find_where("name", Op::EQ, "Jackson");
+    assert_eq!(query.get()[0].1["gpa"], 3.8);
+    assert_eq!(students_index.find_where("gpa", Op::LT, 3.75).count(), 0);
+
+    let snapshot = serde_json::to_value(&students_index).unwrap();
+    assert_eq!(snapshot["str_tree"]["name"]["Jackson"], serde_json::json!(["student:18"]));
+
+    let legacy = serde_json::json!({
+        "indexer": snapshot["indexer"],
+        "int_tree": {},
+        "float_tree": {},
+        "str_tree": {"name": {"Jackson": {"student:18": snapshot["items"]["student:18"]}}},
+        "items": snapshot["items"]
+    });
+    let restored = Index::from(&serde_json::to_vec(&legacy).unwrap()).unwrap();
+    assert_eq!(restored.find_where("state", Op::EQ, "NY").count(), 1);
+}
+
+#[test]
+fn binary_snapshot_round_trip() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC), JsonPathOrder::new("gpa", IndexOrd::DESC)]
+    });
+
+    let mut students_index = Index::new(indexer);
+    for i in 0..20 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 2.0 + i as f64 / 10.0,
+        });
+    }
+
+    let b = students_index.to_vec();
+    assert!(b.starts_with(b"JIDX"));
+    assert!(b.len() < serde_json::to_vec(&students_index).unwrap().len());
+
+    let restored = Index::from(&b).unwrap();
+    assert_eq!(restored.size(), 20);
+    assert_eq!(restored.find_where("state", Op::EQ, "CA").count(), 10);
+    assert_eq!(restored.find_where("gpa", Op::GT, 3.5).count(), 4);
+    assert_eq!(restored.get_items(), students_index.get_items());
+
+    let legacy = serde_json::to_vec(&students_index).unwrap();
+    assert_eq!(Index::from(&legacy).unwrap().size(), 20);
+
+    let mut corrupted = b.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xff;
+    assert!(Index::from(&corrupted).is_err());
+    assert!(Index::from(&b[..b.len() - 4]).is_err());
+}
+
+#[test]
+fn stream_and_save_index_files() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)]
+    });
+
+    let mut students_index = Index::new(indexer);
+    for i in 0..100 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10,
+            state: if i % 4 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 3.0,
+        });
+    }
+
+    let mut b = vec![];
+    students_index.write_to(&mut b).unwrap();
+    let restored = Index::read_from(b.as_slice()).unwrap();
+    assert_eq!(restored.find_where("state", Op::EQ, "CA").count(), 25);
+    assert!(Index::read_from(&b[..b.len() / 2]).is_err());
+
+    let dir = env::temp_dir().join(format!("indexer-save-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("students.idx");
+    students_index.save(&path).unwrap();
+    students_index.remove("student:0");
+    students_index.save(&path).unwrap();
+    assert!(!dir.join("students.idx.tmp").exists());
+
+    let opened = Index::open(&path).unwrap();
+    assert_eq!(opened.size(), 99);
+    assert_eq!(opened.find_where("state", Op::EQ, "CA").count(), 24);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn write_ahead_log_replay_and_compaction() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)]
+    });
+    let dir = env::temp_dir().join(format!("indexer-wal-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let snapshot_path = dir.join("students.idx");
+    let wal_path = dir.join("students.wal");
+
+    {
+        let (mut students_index, _) = Index::recover(&snapshot_path, &wal_path, indexer.clone(), FsyncPolicy::EveryN(8)).unwrap();
+        for i in 0..20 {
+            students_index.insert(&format!("student:{}", i), Student {
+                name: format!("Student {}", i),
+                age: 10,
+                state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+                gpa: 3.0,
+            });
+        }
+        students_index.try_remove("student:0").unwrap();
+        students_index.batch(|b| {
+            b.delete("student:1");
+            b.update("student:2", Student { name: "Student 2".to_owned(), age: 11, state: "NY".to_owned(), gpa: 3.0 });
+            b.try_commit().unwrap()
+        });
+        students_index.sync_wal().unwrap();
+    }
+
+    let (mut recovered, report) = Index::recover(&snapshot_path, &wal_path, indexer.clone(), FsyncPolicy::Always).unwrap();
+    assert_eq!(report, RecoveryReport { recovered: 22, dropped: 0 });
+    assert_eq!(recovered.size(), 18);
+    assert_eq!(recovered.find_where("state", Op::EQ, "CA").count(), 8);
+
+    recovered.compact(&snapshot_path).unwrap();
+    recovered.try_insert("student:20", Student { name: "Student 20".to_owned(), age: 10, state: "CA".to_owned(), gpa: 3.0 }).unwrap();
+    drop(recovered);
+
+    let mut replayed = Index::open(&snapshot_path).unwrap();
+    assert_eq!(replayed.size(), 18);
+    assert_eq!(replayed.attach_wal(&wal_path, FsyncPolicy::Never).unwrap().recovered, 1);
+    assert_eq!(replayed.find_where("state", Op::EQ, "CA").count(), 9);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recover_damaged_files_and_verify() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC), JsonPathOrder::new("age", IndexOrd::ASC)]
+    });
+    let mut students_index = Index::new(indexer.clone());
+    for i in 0..10 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        });
+    }
+    assert!(students_index.verify().is_empty());
+
+    let b = students_index.to_vec();
+    match Index::from(&b[..b.len() - 20]) {
+        Err(PersistError::Truncated { .. }) => {}
+        _ => panic!("expected a truncated snapshot")
+    }
+    let (partial, report) = Index::read_partial(&b[..b.len() - 20]).unwrap();
+    assert_eq!(report, RecoveryReport { recovered: 9, dropped: 1 });
+    assert_eq!(partial.size(), 9);
+    assert!(partial.verify().is_empty());
+
+    let mut corrupted = b.clone();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0xff;
+    assert!(Index::from(&corrupted).err().unwrap().is_damage());
+    let (partial, report) = Index::read_partial(corrupted.as_slice()).unwrap();
+    assert_eq!(report.recovered + report.dropped, 10);
+    assert!(report.dropped > 0);
+    assert_eq!(partial.size(), report.recovered);
+
+    let dir = env::temp_dir().join(format!("indexer-recover-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let wal_path = dir.join("students.wal");
+    {
+        let mut logged = Index::new(indexer.clone());
+        logged.attach_wal(&wal_path, FsyncPolicy::Always).unwrap();
+        for i in 0..5 {
+            logged.insert(&format!("student:{}", i), Student { name: format!("Student {}", i), age: 10, state: "NY".to_owned(), gpa: 3.0 });
+        }
+    }
+    let len = std::fs::metadata(&wal_path).unwrap().len();
+    std::fs::OpenOptions::new().write(true).open(&wal_path).unwrap().set_len(len - 3).unwrap();
+
+    let mut replayed = Index::new(indexer.clone());
+    assert_eq!(replayed.attach_wal(&wal_path, FsyncPolicy::Always).unwrap(), RecoveryReport { recovered: 4, dropped: 1 });
+    replayed.insert("student:5", Student { name: "Student 5".to_owned(), age: 10, state: "NY".to_owned(), gpa: 3.0 });
+    drop(replayed);
+    let mut replayed = Index::new(indexer);
+    assert_eq!(replayed.attach_wal(&wal_path, FsyncPolicy::Always).unwrap(), RecoveryReport { recovered: 5, dropped: 0 });
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    students_index.str_tree.write().unwrap().get_mut("state").unwrap().get_mut("CA").unwrap().remove("student:3");
+    students_index.int_tree.write().unwrap().get_mut("age").unwrap().entry(99).or_default().insert("student:4".to_owned());
+    let found = students_index.verify();
+    assert_eq!(found.len(), 2);
+    assert!(found.contains(&Inconsistency::Missing { key: "student:3".to_owned(), path: "state".to_owned(), value: Value::from("CA") }));
+    assert!(found.contains(&Inconsistency::Stale { key: "student:4".to_owned(), path: "age".to_owned(), value: Value::from(99) }));
+}
+
+#[test]
+fn memory_mapped_index_files() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![
+            JsonPathOrder::new("state", IndexOrd::ASC),
+            JsonPathOrder::new("age", IndexOrd::ASC),
+            JsonPathOrder::new("gpa", IndexOrd::DESC),
+        ]
+    });
+    let states = ["CA", "CO", "NY", "NV", "TX"];
+    let mut students_index = Index::new(indexer);
+    for i in 0..200 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 12) as u8,
+            state: states[i % states.len()].to_owned(),
+            gpa: 2.0 + (i % 20) as f64 / 10.0,
+        });
+    }
+
+    let dir = env::temp_dir().join(format!("indexer-mmap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("students.jidm");
+    students_index.save_mmap(&path).unwrap();
+    let mapped = MmapIndex::open(&path).unwrap();
+    assert_eq!(mapped.size(), 200);
+
+    let keys = |r: QueryResult| {
+        let mut keys: Vec<String> = r.get().iter().map(|(k, _)| k.to_string()).collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(mapped.find_where("state", Op::EQ, "NY")), keys(students_index.find_where("state", Op::EQ, "NY")));
+    assert_eq!(keys(mapped.find_where("state", Op::LIKE, "C*")), keys(students_index.find_where("state", Op::LIKE, "C*")));
+    assert_eq!(mapped.find_where("state", Op::LIKE, "N?").count(), 80);
+    assert_eq!(keys(mapped.find_where("age", Op::LT, 13)), keys(students_index.find_where("age", Op::LT, 13)));
+    assert_eq!(keys(mapped.find_where("/age", Op::GT, 20)), keys(students_index.find_where("age", Op::GT, 20)));
+    assert_eq!(keys(mapped.find_where("gpa", Op::GT, 3.5)), keys(students_index.find_where("gpa", Op::GT, 3.5)));
+    assert_eq!(mapped.find_where("state", Op::EQ, "WA").count(), 0);
+    assert_eq!(mapped.find_where("name", Op::EQ, "Student 1").count(), 0);
+
+    let mut ny = mapped.find_where("state", Op::EQ, "NY");
+    let ordered = ny.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("gpa", IndexOrd::DESC)] }));
+    assert_eq!(ordered.get()[0].1["gpa"], 3.7);
+    drop(mapped);
+
+    std::fs::write(&path, b"not an index").unwrap();
+    assert!(MmapIndex::open(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn collection_keeps_indexes_consistent() {
+    let mut students = Collection::new();
+    students.insert("student:0", Student { name: "Kwadwo".to_owned(), age: 12, state: "CA".to_owned(), gpa: 3.1 });
+    students.add_index("state", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)] }));
+    students.add_index("age", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    for i in 1..20 {
+        students.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 5) as u8,
+            state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 3.0,
+        });
+    }
+    students.insert("teacher:0", serde_json::json!({"name": "Ama", "state": "CA"}));
+    assert_eq!(students.size(), 21);
+    students.indexes().for_each(|(_, index)| assert!(index.verify().is_empty()));
+    assert_eq!(students.index_names(), vec!["state", "age"]);
+
+    let state = |c: &Collection, s: &str| c.index("state").unwrap().find_where("state", Op::EQ, s).count();
+    let age = |c: &Collection, a: i64| c.index("age").unwrap().find_where("age", Op::EQ, a).count();
+    assert_eq!(state(&students, "CA"), 11);
+    assert_eq!(age(&students, 12), 5);
+
+    students.insert("student:0", Student { name: "Kwadwo".to_owned(), age: 13, state: "NY".to_owned(), gpa: 3.1 });
+    assert!(students.update("student:1", Student { name: "Student 1".to_owned(), age: 12, state: "TX".to_owned(), gpa: 3.0 }));
+    assert!(!students.update("student:99", Student { name: "Nobody".to_owned(), age: 12, state: "TX".to_owned(), gpa: 3.0 }));
+    assert_eq!(students.remove("teacher:0").unwrap()["name"], "Ama");
+    assert_eq!(state(&students, "CA"), 9);
+    assert_eq!(state(&students, "TX"), 1);
+    assert_eq!(age(&students, 12), 5);
+
+    students.batch(|b| {
+        b.insert("student:20", Student { name: "Student 20".to_owned(), age: 12, state: "CA".to_owned(), gpa: 3.0 });
+        b.update("student:2", Student { name: "Student 2".to_owned(), age: 18, state: "TX".to_owned(), gpa: 3.0 });
+        b.update("student:98", Student { name: "Nobody".to_owned(), age: 18, state: "TX".to_owned(), gpa: 3.0 });
+        b.delete("student:3");
+        b.commit()
+    });
+    assert_eq!(students.size(), 20);
+    assert_eq!(state(&students, "CA"), 9);
+    assert_eq!(state(&students, "TX"), 2);
+    assert_eq!(age(&students, 18), 1);
+    students.indexes().for_each(|(_, index)| assert!(index.verify().is_empty()));
+
+    // the indexes refuse changes, even through a clone, which would leave the other indexes out of date
+    let mut clone = students.index("age").unwrap().clone();
+    assert_eq!(clone.try_insert("student:21", serde_json::json!({"age": 12, "state": "CA"})).unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    clone.remove("student:4");
+    assert_eq!(students.size(), 20);
+    students.indexes().for_each(|(_, index)| assert!(index.verify().is_empty()));
+
+    assert!(students.drop_index("age"));
+    assert!(students.index("age").is_none());
+}
+
+#[test]
+fn planner_picks_selective_indexes() {
+    let mut students = Collection::new();
+    students.add_index("state", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)] }));
+    students.add_index("age", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    students.add_index("gpa", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("gpa", IndexOrd::ASC)] }));
+    for i in 0..300 {
+        students.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 10) as u8,
+            state: if i % 100 == 0 { "WA".to_owned() } else if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: (i % 40) as f64 / 10.0,
+        });
+    }
+    let keys = |r: QueryResult| {
+        let mut keys: Vec<String> = r.get().iter().map(|(k, _)| k.to_string()).collect();
+        keys.sort();
+        keys
+    };
+    let scanned = |q: &Query| {
+        let mut keys: Vec<String> = (0..300).map(|i| format!("student:{}", i))
+            .filter(|k| q.matches(&students.get(k).unwrap())).collect();
+        keys.sort();
+        keys
+    };
+
+    let q = Query::and(vec![
+        Query::cond("age", Op::LT, 15),
+        Query::cond("state", Op::EQ, "WA"),
+        Query::cond("name", Op::LIKE, "Student 1*"),
+    ]);
+    match students.plan(&q) {
+        Plan::Filter { plan, predicate, .. } => {
+            match *plan {
+                Plan::IndexScan { index, estimate, .. } => {
+                    assert_eq!(index, "state");
+                    assert_eq!(estimate, 3);
+                }
+                p => panic!("expected an index scan, got {:?}", p)
+            }
+            assert_eq!(predicate, Query::and(vec![Query::cond("name", Op::LIKE, "Student 1*"), Query::cond("age", Op::LT, 15)]));
+        }
+        p => panic!("expected a filter, got {:?}", p)
+    }
+    assert_eq!(keys(students.find(&q)), scanned(&q));
+    assert_eq!(keys(students.find(&q)), vec!["student:100"]);
+
+    let q = Query::and(vec![Query::cond("age", Op::EQ, 12), Query::cond("gpa", Op::GT, 3.0)]);
+    match students.plan(&q) {
+        Plan::Intersect { plans, estimate } => {
+            assert_eq!(plans.len(), 2);
+            assert_eq!(estimate, 30);
+        }
+        p => panic!("expected an intersection, got {:?}", p)
+    }
+    assert_eq!(keys(students.find(&q)), scanned(&q));
+    // results come in the order of the documents, whatever the plan
+    let found: Vec<String> = students.find(&q).get().iter().map(|(k, _)| k.to_string()).collect();
+    let in_order: Vec<String> = (0..300).map(|i| format!("student:{}", i)).filter(|k| found.contains(k)).collect();
+    assert_eq!(found, in_order);
+
+    let q = Query::or(vec![Query::cond("state", Op::EQ, "WA"), Query::cond("/age", Op::GT, 18)]);
+    assert!(matches!(students.plan(&q), Plan::Union { .. }));
+    assert_eq!(keys(students.find(&q)), scanned(&q));
+
+    let q = Query::or(vec![Query::cond("state", Op::EQ, "WA"), Query::cond("name", Op::EQ, "Student 7")]);
+    assert!(students.plan(&q).is_full_scan());
+    assert_eq!(students.find(&q).count(), 4);
+
+    let q = Query::and(vec![!Query::cond("state", Op::LIKE, "?A"), Query::cond("age", Op::EQ, 11)]);
+    assert_eq!(keys(students.find(&q)), scanned(&q));
+    assert_eq!(students.find(&q).count(), 30);
+
+    let mut ages = Index::new(Indexer::Integer(IndexInt { ordering: IndexOrd::ASC }));
+    (0..50).for_each(|i| ages.insert(&format!("user.{}", i), i));
+    assert!(matches!(ages.plan(&Query::cond("*", Op::GT, 40)), Plan::IndexScan { estimate: 9, .. }));
+    assert_eq!(ages.find(&!Query::cond("*", Op::LT, 45)).count(), 5);
+}
+
+#[test]
+fn explain_reports_tree_walk() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC), JsonPathOrder::new("age", IndexOrd::ASC)]
+    });
+    let states = ["CA", "CO", "NY", "NV"];
+    let mut students_index = Index::new(indexer);
+    for i in 0..40 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 8) as u8,
+            state: states[i % states.len()].to_owned(),
+            gpa: 3.0,
+        });
+    }
+
+    let result = students_index.find_where("state", Op::LIKE, "C*");
+    let explain = result.explain();
+    assert_eq!(explain.tree.as_deref(), Some("str_tree"));
+    assert_eq!(explain.path.as_deref(), Some("state"));
+    assert_eq!(explain.like_prefix.as_deref(), Some("C"));
+    assert_eq!(explain.lower, std::ops::Bound::Included(Value::from("C")));
+    assert_eq!(explain.upper, std::ops::Bound::Excluded(Value::from("D")));
+    assert_eq!(explain.buckets_visited, 2);
+    assert_eq!(explain.documents_examined, 20);
+    assert_eq!(explain.documents_returned, 20);
+    assert!(!explain.sorted_by_tree);
+
+    let mut result = students_index.find_where("/age", Op::GT, 15);
+    let explain = result.explain();
+    assert_eq!(explain.tree.as_deref(), Some("int_tree"));
+    assert_eq!(explain.lower, std::ops::Bound::Excluded(Value::from(15)));
+    assert_eq!(explain.upper, std::ops::Bound::Unbounded);
+    assert_eq!((explain.buckets_visited, explain.documents_returned), (2, 10));
+    result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    assert!(result.explain().sorted_by_tree);
+    let mut result = students_index.find_where("/age", Op::GT, 15);
+    result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] })).limit(2);
+    let explain = result.explain();
+    assert!(explain.sorted_by_tree);
+    assert_eq!((explain.buckets_visited, explain.documents_examined, explain.documents_returned), (2, 10, 2));
+    let mut result = students_index.find_where("/age", Op::GT, 15);
+    result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)] }));
+    assert!(!result.explain().sorted_by_tree);
+
+    let explain = students_index.find_where("state", Op::EQ, 3.5).explain();
+    assert_eq!(explain.tree.as_deref(), Some("float_tree"));
+    assert_eq!(explain.documents_returned, 0);
+    let json = serde_json::to_value(&explain).unwrap();
+    assert_eq!(json["op"], "EQ");
+}
+
+#[test]
+fn index_statistics() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![
+            JsonPathOrder::new("state", IndexOrd::ASC),
+            JsonPathOrder::new("age", IndexOrd::ASC),
+            JsonPathOrder::new("gpa", IndexOrd::ASC),
+        ]
+    });
+    let mut students_index = Index::new(indexer);
+    for i in 0..30 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 3) as u8,
+            state: if i < 20 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 2.5 + (i % 6) as f64 / 4.0,
+        });
+    }
+
+    let stats = students_index.stats();
+    assert_eq!(stats.documents, 30);
+    let paths: Vec<(&str, &str)> = stats.paths.iter().map(|p| (p.path.as_str(), p.tree.as_str())).collect();
+    assert_eq!(paths, vec![("age", "int_tree"), ("gpa", "float_tree"), ("state", "str_tree")]);
+
+    let age = &stats.paths[0];
+    assert_eq!((age.distinct_keys, age.documents, age.entries), (3, 30, 30));
+    assert_eq!((age.min_key.clone(), age.max_key.clone()), (Some(Value::from(10)), Some(Value::from(12))));
+    assert_eq!((age.bucket_sizes.min, age.bucket_sizes.max, age.bucket_sizes.mean), (10, 10, 10.0));
+    assert_eq!(age.bucket_sizes.histogram, vec![0, 0, 0, 0, 3]);
+
+    let gpa = &stats.paths[1];
+    assert_eq!(gpa.distinct_keys, 6);
+    assert_eq!(gpa.max_key, Some(Value::from(3.75)));
+
+    let state = &stats.paths[2];
+    assert_eq!((state.bucket_sizes.min, state.bucket_sizes.max), (10, 20));
+    assert_eq!(state.bucket_sizes.histogram, vec![0, 0, 0, 0, 1, 1]);
+    assert_eq!(state.min_key, Some(Value::from("CA")));
+
+    assert!(stats.memory_bytes > stats.items_memory_bytes);
+    assert!(stats.paths.iter().all(|p| p.memory_bytes > 0));
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["paths"][2]["distinct_keys"], 2);
+}
+
+#[test]
+fn aggregate_numeric_paths() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("gpa", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer);
+    for i in 0..10 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: if i < 6 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 2.0 + i as f64 / 4.0,
+        });
+    }
+
+    assert_eq!(students_index.aggregate("gpa", Agg::Count), Some(Value::from(10)));
+    assert_eq!(students_index.aggregate("gpa", Agg::Min), Some(Value::from(2.0)));
+    assert_eq!(students_index.aggregate("$.gpa", Agg::Max), Some(Value::from(4.25)));
+    assert_eq!(students_index.aggregate("gpa", Agg::Sum), Some(Value::from(31.25)));
+    assert_eq!(students_index.aggregate("age", Agg::Sum), Some(Value::from(145)));
+    assert_eq!(students_index.aggregate("age", Agg::Avg), Some(Value::from(14.5)));
+    assert_eq!(students_index.aggregate("state", Agg::Count), Some(Value::from(0)));
+    assert_eq!(students_index.aggregate("state", Agg::Max), None);
+
+    let result = students_index.find_where("gpa", Op::GT, 3.0);
+    assert_eq!(result.aggregate("age", Agg::Min), Some(Value::from(15)));
+    assert_eq!(result.aggregate("age", Agg::Max), Some(Value::from(19)));
+    assert_eq!(result.aggregate("/gpa", Agg::Avg), Some(Value::from(3.75)));
+
+    students_index.remove("student:9");
+    assert_eq!(students_index.aggregate("gpa", Agg::Max), Some(Value::from(4.0)));
+
+    // a value repeated in one document counts once, whether read from the trees or from the documents
+    let mut scores = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("$.n[*]", IndexOrd::ASC)] }));
+    scores.insert("a", serde_json::json!({"n": [5, 5, 7]}));
+    scores.insert("b", serde_json::json!({"n": [5]}));
+    assert_eq!(scores.aggregate("$.n[*]", Agg::Sum), Some(Value::from(17)));
+    assert_eq!(scores.find_where("$.n[*]", Op::GT, 0).aggregate("$.n[*]", Agg::Sum), Some(Value::from(17)));
+    assert_eq!(scores.find_where("$.n[*]", Op::GT, 0).aggregate("n", Agg::Count), Some(Value::from(3)));
+}
+
+#[test]
+fn group_by_with_aggregates() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer);
+    for i in 0..12 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: ["CA", "NY", "TX", "CA"][i % 4].to_owned(),
+            gpa: 2.0 + (i % 3) as f64 / 2.0,
+        });
+    }
+
+    let groups = students_index.group_by("state").get();
+    let keys: Vec<(Value, usize)> = groups.iter().map(|g| (g.key.clone(), g.count)).collect();
+    assert_eq!(keys, vec![(Value::from("CA"), 6), (Value::from("NY"), 3), (Value::from("TX"), 3)]);
+    assert!(groups[0].aggregates.is_empty());
+
+    let groups = students_index.group_by("state")
+        .aggregate("gpa", Agg::Sum)
+        .aggregate("age", Agg::Max)
+        .order_by_aggregate(1, IndexOrd::DESC)
+        .get();
+    let keys: Vec<&str> = groups.iter().map(|g| g.key.as_str().unwrap()).collect();
+    assert_eq!(keys, vec!["CA", "TX", "NY"]);
+    assert_eq!(groups[0].aggregates, vec![Some(Value::from(15.0)), Some(Value::from(21))]);
+
+    let groups = students_index.group_by("state").order_by_key(IndexOrd::DESC).get();
+    assert_eq!(groups[0].key, Value::from("TX"));
+
+    // paths the trees do not answer are grouped from the documents
+    let groups = students_index.group_by("gpa").aggregate("age", Agg::Count).order_by_count(IndexOrd::ASC).get();
+    let keys: Vec<(Value, usize)> = groups.iter().map(|g| (g.key.clone(), g.count)).collect();
+    assert_eq!(keys, vec![(Value::from(2.0), 4), (Value::from(2.5), 4), (Value::from(3.0), 4)]);
+    assert_eq!(groups[1].aggregates, vec![Some(Value::from(4))]);
+}
+
+#[test]
+fn distinct_values_and_facets() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("state", IndexOrd::ASC), JsonPathOrder::new("age", IndexOrd::ASC)]
+    });
+    let mut students_index = Index::new(indexer);
+    for i in 0..20 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 5) as u8,
+            state: ["CA", "NY", "CA", "TX"][i % 4].to_owned(),
+            gpa: 2.0 + (i % 8) as f64 / 4.0,
+        });
+    }
+
+    assert_eq!(students_index.distinct("state"), vec![Value::from("CA"), Value::from("NY"), Value::from("TX")]);
+    assert_eq!(students_index.distinct("age").len(), 5);
+    assert_eq!(students_index.distinct("gpa").first(), Some(&Value::from(2.0)));
+
+    let result = students_index.find_where("age", Op::GT, 12);
+    assert_eq!(result.count(), 8);
+    assert_eq!(result.distinct("age"), vec![Value::from(13), Value::from(14)]);
+
+    let facets = result.facets(&[Facet::values("state"), Facet::top("age", 1), Facet::ranges("gpa", &[3.0, 2.5])]);
+    let state: Vec<(&str, usize)> = facets["state"].iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    assert_eq!(state, vec![("CA", 4), ("NY", 2), ("TX", 2)]);
+    assert_eq!(facets["age"].len(), 1);
+    let gpa: Vec<(&str, usize)> = facets["gpa"].iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    assert_eq!(gpa, vec![("..2.5", 2), ("2.5..3", 3), ("3..", 3)]);
+
+    // a result with fewer matches than the path has keys reads its own documents
+    let few = students_index.find_where("age", Op::EQ, 14);
+    assert!(few.count() < students_index.distinct("age").len());
+    assert_eq!(few.distinct("age"), vec![Value::from(14)]);
+    assert_eq!(few.facets(&[Facet::values("age")])["age"]["14"], few.count());
+
+    let facets = students_index.facets(&[Facet::top("state", 2)]);
+    let state: Vec<(&str, usize)> = facets["state"].iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    assert_eq!(state, vec![("CA", 10), ("NY", 5)]);
+}
+
+#[test]
+fn histograms_and_percentiles() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("gpa", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer);
+    for i in 0..20 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 10) as u8,
+            state: "CA".to_owned(),
+            gpa: if i < 10 { 2.0 } else { 2.0 + (i - 9) as f64 / 5.0 },
+        });
+    }
+
+    let width = students_index.histogram("gpa", Histogram::EquiWidth(2));
+    assert_eq!(width, vec![
+        HistogramBucket { lower: 2.0, upper: 3.0, count: 14 },
+        HistogramBucket { lower: 3.0, upper: 4.0, count: 6 },
+    ]);
+    let depth = students_index.histogram("gpa", Histogram::EquiDepth(4));
+    let counts: Vec<usize> = depth.iter().map(|b| b.count).collect();
+    assert_eq!(counts, vec![10, 5, 5]);
+    assert_eq!((depth[1].lower, depth[1].upper), (2.2, 3.0));
+    assert_eq!(students_index.histogram("age", Histogram::EquiWidth(5)).iter().map(|b| b.count).sum::<usize>(), 20);
+    assert!(students_index.histogram("state", Histogram::EquiDepth(3)).is_empty());
+
+    assert_eq!(students_index.percentile("gpa", 0.5), Some(Value::from(2.0)));
+    assert_eq!(students_index.percentiles("gpa", &[0.0, 0.95, 1.0, 1.5]), vec![
+        Some(Value::from(2.0)), Some(Value::from(3.8)), Some(Value::from(4.0)), None
+    ]);
+    assert_eq!(students_index.percentile("age", 0.75), Some(Value::from(17)));
+}
+
+#[test]
+fn sketches_track_distinct_counts_and_quantiles() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC), JsonPathOrder::new("name", IndexOrd::ASC)]
+    });
+    let mut students_index = Index::new(indexer.clone());
+    students_index.add_sketch("age");
+    students_index.add_sketch("$.name");
+    assert_eq!(students_index.sketch_paths(), vec!["age".to_string(), "name".to_string()]);
+    for i in 0..5000 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: (i % 100) as u8,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        });
+    }
+
+    let names = students_index.sketch("name").unwrap();
+    let error = (names.distinct_count() as f64 - 5000.0).abs() / 5000.0;
+    assert!(error < 0.03, "distinct count {}", names.distinct_count());
+    let ages = students_index.sketch("age").unwrap();
+    assert_eq!(ages.distinct_count(), 100);
+    assert!((ages.quantile(0.5).unwrap() - 49.5).abs() < 2.0);
+    assert!((ages.quantile(0.99).unwrap() - 99.0).abs() < 2.0);
+    assert_eq!(ages.quantile(0.0), Some(0.0));
+    assert!(students_index.sketch("state").is_none());
+
+    // removals are not applied to the sketch, it is rebuilt from the trees when read
+    for i in 0..5000 {
+        if i % 100 >= 50 {
+            students_index.remove(&format!("student:{}", i));
+        }
+    }
+    let ages = students_index.sketch("age").unwrap();
+    assert_eq!(ages.distinct_count(), 50);
+    assert!((ages.quantile(0.5).unwrap() - 24.5).abs() < 2.0);
+
+    let mut other = Index::new(indexer);
+    other.add_sketch("age");
+    for i in 0..100 {
+        other.insert(&format!("teacher:{}", i), serde_json::json!({"age": 40 + i, "name": format!("Teacher {}", i)}));
+    }
+    let mut merged = ages.clone();
+    merged.merge(&other.sketch("age").unwrap());
+    assert_eq!(merged.distinct_count(), 140);
+    assert!(merged.quantile(1.0).unwrap() >= 139.0);
+
+    let restored = Index::from(&students_index.to_vec()).unwrap();
+    assert_eq!(restored.sketch_paths(), students_index.sketch_paths());
+    assert!(!restored.sketches.read().unwrap()["age"].is_dirty());
+    assert_eq!(restored.sketch("age").unwrap(), ages);
+
+    // replacing a document only dirties the sketches of the paths whose values changed
+    students_index.insert("student:1", serde_json::json!({"age": 1, "name": "Renamed"}));
+    assert!(!students_index.sketches.read().unwrap()["age"].is_dirty());
+    assert!(students_index.sketches.read().unwrap()["name"].is_dirty());
+}
+
+#[test]
+fn set_operations_between_results() {
+    let indexer = Indexer::Json(IndexJson {
+        path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC), JsonPathOrder::new("state", IndexOrd::ASC)]
+    });
+    let mut students_index = Index::new(indexer);
+    for i in 0..10 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 3.0,
+        });
+    }
+    let keys = |r: &QueryResult| -> Vec<String> { r.get().iter().map(|(k, _)| k.to_string()).collect() };
+    let sorted_keys = |r: &QueryResult| -> Vec<String> {
+        let mut keys = keys(r);
+        keys.sort();
+        keys
+    };
+
+    let older = students_index.find_where("age", Op::GT, 15);
+    let new_york = students_index.find_where("state", Op::EQ, "NY");
+    assert_eq!(older.union(&new_york).count(), 7);
+    assert_eq!(sorted_keys(&older.intersect(&new_york)), vec!["student:7", "student:9"]);
+    assert_eq!(sorted_keys(&older.difference(&new_york)), vec!["student:6", "student:8"]);
+    let symmetric = older.symmetric_difference(&new_york);
+    assert_eq!(sorted_keys(&symmetric), vec!["student:1", "student:3", "student:5", "student:6", "student:8"]);
+    assert_eq!(keys(&symmetric)[..2], keys(&older.difference(&new_york))[..]);
+    assert_eq!(new_york.difference(&new_york).count(), 0);
+
+    // the order of an ordered result is applied to the combined documents
+    let mut older = students_index.find_where("age", Op::GT, 15);
+    older.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    let union = older.union(&new_york);
+    assert_eq!(keys(&union), vec!["student:9", "student:8", "student:7", "student:6", "student:5", "student:3", "student:1"]);
+    assert_eq!(keys(&union.difference(&older)), vec!["student:5", "student:3", "student:1"]);
+}
+
+#[test]
+fn join_results_with_another_index() {
+    let mut states_index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("code", IndexOrd::ASC)] }));
+    states_index.insert("state:ca", serde_json::json!({"code": "CA", "name": "California"}));
+    states_index.insert("state:ny", serde_json::json!({"code": "NY", "name": "New York"}));
+    states_index.insert("state:ny-2", serde_json::json!({"code": "NY", "name": "New York State"}));
+    let mut students_index = Index::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    for (i, state) in ["CA", "NY", "TX"].iter().enumerate() {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: state.to_string(),
+            gpa: 3.0,
+        });
+    }
+    let mut result = students_index.find_where("age", Op::GT, 0);
+    result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+    let rows = |kind| -> Vec<(String, Option<String>)> {
+        result.join("state", &states_index, "code", kind).into_iter().map(|row| (row.key, row.right.map(|(k, _)| k))).collect()
+    };
+
+    assert_eq!(rows(Join::Inner), vec![
+        ("student:0".to_string(), Some("state:ca".to_string())),
+        ("student:1".to_string(), Some("state:ny".to_string())),
+        ("student:1".to_string(), Some("state:ny-2".to_string())),
+    ]);
+    assert_eq!(rows(Join::Left).len(), 4);
+    assert_eq!(rows(Join::Left)[3], ("student:2".to_string(), None));
+    assert_eq!(rows(Join::Semi), vec![("student:0".to_string(), None), ("student:1".to_string(), None)]);
+
+    let joined = result.join("state", &states_index, "code", Join::Inner);
+    assert_eq!(joined[0].left["name"], "Student 0");
+    assert_eq!(joined[0].right.as_ref().unwrap().1["name"], "California");
+
+    // a path the trees of the other index do not hold is matched against its documents
+    let by_name = students_index.find_where("age", Op::EQ, 11).join("state", &states_index, "$.code", Join::Inner);
+    assert_eq!(by_name.len(), 2);
+    let by_name = students_index.find_where("age", Op::EQ, 11).join("name", &states_index, "name", Join::Left);
+    assert_eq!(by_name, vec![JoinRow { key: "student:1".to_string(), left: by_name[0].left.clone(), right: None }]);
+}
+
+#[test]
+fn project_returned_documents() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer);
+    for i in 0..6 {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: if i % 2 == 0 { "CA".to_owned() } else { "NY".to_owned() },
+            gpa: 3.0,
+        });
+    }
+
+    let older = students_index.find_where("age", Op::GT, 12);
+    let names = Projection::new().include("name").rename("/state", "address.state");
+    let mut projected = older.project(&names);
+    projected.sort_by(|(a, _), (b, _)| a.cmp(b));
+    assert_eq!(projected, vec![
+        ("student:3".to_string(), serde_json::json!({"name": "Student 3", "address": {"state": "NY"}})),
+        ("student:4".to_string(), serde_json::json!({"name": "Student 4", "address": {"state": "CA"}})),
+        ("student:5".to_string(), serde_json::json!({"name": "Student 5", "address": {"state": "NY"}})),
+    ]);
+    let without = older.project(&Projection::new().exclude("gpa").exclude("name"));
+    let student_3 = without.iter().find(|(k, _)| k == "student:3").unwrap();
+    assert_eq!(student_3.1, serde_json::json!({"age": 13, "state": "NY"}));
+
+    // results ordered by walking the tree are projected in that order
+    let mut older = students_index.find_where("age", Op::GT, 12);
+    older.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    let ages = Projection::new().include("name").rename("age", "years");
+    let projected: Vec<Value> = older.project(&ages).into_iter().map(|(_, v)| v).collect();
+    assert_eq!(projected, vec![
+        serde_json::json!({"name": "Student 5", "years": 15}),
+        serde_json::json!({"name": "Student 4", "years": 14}),
+        serde_json::json!({"name": "Student 3", "years": 13}),
+    ]);
+
+    // a materialized result is projected in its own order
+    let mut by_name = students_index.find_where("age", Op::LT, 13);
+    by_name.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("name", IndexOrd::DESC)] }));
+    let keys: Vec<String> = by_name.project(&ages).into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec!["student:2", "student:1", "student:0"]);
+
+    // a page is projected as it is
+    let mut older = students_index.find_where("age", Op::GT, 10);
+    older.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] })).limit(2);
+    let names: Vec<Value> = older.project(&Projection::new().include("name")).into_iter().map(|(_, v)| v).collect();
+    assert_eq!(names, vec![serde_json::json!({"name": "Student 1"}), serde_json::json!({"name": "Student 2"})]);
+}
+
+#[test]
+fn typed_results_and_index() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] });
+    let mut students_index: TypedIndex<Student> = TypedIndex::new(indexer);
+    for i in 0..4 {
+        students_index.insert(&format!("student:{}", i), &Student {
+            name: format!("Student {}", i),
+            age: 10 + i as u8,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        }).unwrap();
+    }
+    // documents written through the untyped index may not fit the type
+    students_index.index_mut().insert("student:9", serde_json::json!({"name": "Student 9", "age": 300}));
+    assert_eq!(students_index.size(), 5);
+
+    let older = students_index.find_where("age", Op::GT, 11);
+    assert_eq!(older.len(), 3);
+    let mut names: Vec<String> = older.iter().filter_map(|r| r.as_ref().ok()).map(|(_, s)| s.name.to_string()).collect();
+    names.sort();
+    assert_eq!(names, vec!["Student 2", "Student 3"]);
+    let errors: Vec<&DocumentError> = older.iter().filter_map(|r| r.as_ref().err()).collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].key, "student:9");
+    assert!(matches!(errors[0].kind, DocumentErrorKind::Serde(_)));
+    assert!(errors[0].to_string().starts_with("document student:9: "));
+
+    assert_eq!(students_index.get("student:1").unwrap().unwrap().age, 11);
+    assert!(students_index.get("student:9").unwrap().is_err());
+    assert!(students_index.get("student:7").is_none());
+    students_index.remove("student:9");
+    assert_eq!(students_index.find(&Query::cond("age", Op::GT, 11)).len(), 2);
+
+    let mut result = students_index.index().find_where("age", Op::LT, 12);
+    let ordered = result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    let students = ordered.get_as::<Student>();
+    let ages: Vec<u8> = students.into_iter().map(|r| r.unwrap().1.age).collect();
+    assert_eq!(ages, vec![11, 10]);
+
+    // a document the indexer does not accept is reported instead of being dropped
+    let mut by_email: TypedIndex<Student> = TypedIndex::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("email", IndexOrd::ASC)] }));
+    let error = by_email.insert("student:1", &Student { name: "Student 1".to_owned(), age: 11, state: "CA".to_owned(), gpa: 3.0 }).unwrap_err();
+    assert_eq!(error.key, "student:1");
+    assert!(matches!(error.kind, DocumentErrorKind::Rejected));
+    assert_eq!(by_email.size(), 0);
+}
+
+#[test]
+fn point_lookups_and_key_scans() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer.clone());
+    for i in (0..30).rev() {
+        students_index.insert(&format!("student:{}", i), Student {
+            name: format!("Student {}", i),
+            age: 10 + (i % 5) as u8,
+            state: "CA".to_owned(),
+            gpa: 3.0,
+        });
+    }
+    students_index.insert("teacher:1", serde_json::json!({"name": "Teacher 1", "age": 40}));
+    let keys = |docs: Vec<(String, Value)>| -> Vec<String> { docs.into_iter().map(|(k, _)| k).collect() };
+
+    assert_eq!(students_index.get("student:7").unwrap()["name"], "Student 7");
+    assert!(students_index.contains_key("teacher:1"));
+    assert!(students_index.get("student:30").is_none());
+    assert_eq!(keys(students_index.scan_keys("student:10".."student:13")), vec!["student:10", "student:11", "student:12"]);
+    assert_eq!(keys(students_index.scan_keys("student:28"..="student:3")), vec!["student:28", "student:29", "student:3"]);
+    assert_eq!(students_index.scan_prefix("student:").len(), 30);
+    assert_eq!(keys(students_index.scan_prefix("teacher")), vec!["teacher:1"]);
+    assert_eq!(students_index.scan_prefix("").len(), 31);
+    assert!(students_index.scan_keys("student:13".."student:10").is_empty());
+    assert!(students_index.scan_keys("student:10".."student:10").is_empty());
+
+    students_index.remove("student:11");
+    students_index.batch(|b| {
+        b.delete("student:12");
+        b.insert("student:115", serde_json::json!({"age": 12}));
+        b.commit()
+    });
+    assert!(!students_index.contains_key("student:11"));
+    assert_eq!(keys(students_index.scan_keys("student:10".."student:13")), vec!["student:10", "student:115"]);
+
+    let restored = Index::from(&students_index.to_vec()).unwrap();
+    assert_eq!(keys(restored.scan_keys("student:10".."student:13")), vec!["student:10", "student:115"]);
+
+    let mut collection = Collection::new();
+    collection.add_index("age", indexer);
+    collection.insert("b", serde_json::json!({"age": 1}));
+    collection.insert("a", serde_json::json!({"name": "not indexed"}));
+    collection.insert("c", serde_json::json!({"age": 3}));
+    collection.remove("c");
+    let index = collection.index("age").unwrap();
+    assert_eq!(keys(index.scan_prefix("")), vec!["a", "b"]);
+
+    // the indexes of a collection share one key set
+    collection.add_index("name", Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("name", IndexOrd::ASC)] }));
+    collection.batch(|b| {
+        b.insert("d", serde_json::json!({"age": 4}));
+        b.delete("a");
+        b.commit()
+    });
+    let (age, name) = (collection.index("age").unwrap(), collection.index("name").unwrap());
+    assert!(Arc::ptr_eq(&age.keys, &name.keys));
+    assert_eq!(keys(name.scan_prefix("")), vec!["b", "d"]);
+}
+
+#[test]
+fn query_results_hold_the_matches_of_the_query() {
+    let indexer = Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] });
+    let mut students_index = Index::new(indexer);
+    students_index.insert("student:1", serde_json::json!({"age": 3}));
+    students_index.insert("student:2", serde_json::json!({"age": 4}));
+
+    let mut result = students_index.find_where("age", Op::EQ, 3);
+    students_index.insert("late", serde_json::json!({"age": 3}));
+    students_index.remove("student:1");
+
+    assert_eq!(result.count(), 1);
+    let keys: Vec<&str> = result.get().iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, vec!["student:1"]);
+    // ordering by the queried path walks the tree, a match whose document changed since is still returned
+    let ordered = result.order_by(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::DESC)] }));
+    assert_eq!(ordered.get()[0].0, "student:1");
+}
@@ -0,0 +1,138 @@
+//! Typed access to the documents of an index.
+
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+use crate::{Index, Indexer, Op, Query};
+
+/// A document that could not be converted to or from the requested type, or that the indexer does not accept
+#[derive(Debug)]
+pub struct DocumentError {
+    pub key: String,
+    pub kind: DocumentErrorKind,
+}
+
+/// Why a document was refused, see [`DocumentError`]
+#[derive(Debug)]
+pub enum DocumentErrorKind {
+    /// the document does not convert to or from the requested type
+    Serde(serde_json::Error),
+    /// the indexer does not accept the document, e.g. a path of a json indexer is missing
+    Rejected,
+}
+
+impl DocumentError {
+    fn serde(key: &str, error: serde_json::Error) -> Self {
+        DocumentError { key: key.to_string(), kind: DocumentErrorKind::Serde(error) }
+    }
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            DocumentErrorKind::Serde(e) => write!(f, "document {}: {}", self.key, e),
+            DocumentErrorKind::Rejected => write!(f, "document {}: the indexer does not accept the document", self.key),
+        }
+    }
+}
+
+impl error::Error for DocumentError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            DocumentErrorKind::Serde(e) => Some(e),
+            DocumentErrorKind::Rejected => None,
+        }
+    }
+}
+
+/// Converts matches to `T`, one result per document
+pub(crate) fn decode<T: DeserializeOwned>(matches: &[(String, Value)]) -> Vec<Result<(String, T), DocumentError>> {
+    matches.iter().map(|(k, v)| {
+        T::deserialize(v).map(|t| (k.to_string(), t)).map_err(|error| DocumentError::serde(k, error))
+    }).collect()
+}
+
+/// An [`Index`] of documents of type `T`. documents are serialized on insert and deserialized when returned, a
+/// document stored through the untyped index that does not fit `T` is reported as a [`DocumentError`]
+/// ## Example
+/// ```rust
+/// use indexer::{TypedIndex, Indexer, IndexJson, JsonPathOrder, IndexOrd, Op};
+/// use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct User {
+///     name: String,
+///     age: u8,
+/// }
+/// let mut users: TypedIndex<User> = TypedIndex::new(Indexer::Json(IndexJson { path_orders: vec![JsonPathOrder::new("age", IndexOrd::ASC)] }));
+/// users.insert("user.1", &User { name: "Kwadwo".to_string(), age: 21 }).unwrap();
+/// users.insert("user.2", &User { name: "Kwame".to_string(), age: 16 }).unwrap();
+/// let adults = users.find_where("age", Op::GT, 18);
+/// assert_eq!(adults[0].as_ref().unwrap().1.name, "Kwadwo");
+/// assert_eq!(users.get("user.2").unwrap().unwrap().age, 16);
+/// ```
+pub struct TypedIndex<T> {
+    index: Index,
+    marker: PhantomData<T>,
+}
+
+impl<T> TypedIndex<T> where T: Serialize + DeserializeOwned {
+    pub fn new(indexer: Indexer) -> Self {
+        TypedIndex::from_index(Index::new(indexer))
+    }
+
+    /// Wraps an existing index, its documents are checked when they are read
+    pub fn from_index(index: Index) -> Self {
+        TypedIndex { index, marker: PhantomData }
+    }
+
+    /// The untyped index, for ordering, aggregates and persistence
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn index_mut(&mut self) -> &mut Index {
+        &mut self.index
+    }
+
+    pub fn into_inner(self) -> Index {
+        self.index
+    }
+
+    /// Inserts or replaces a document, fails without changing the index when `value` does not serialize to JSON or
+    /// the indexer does not accept it, e.g. when a path of a json indexer is missing
+    pub fn insert(&mut self, key: &str, value: &T) -> Result<(), DocumentError> {
+        let v = serde_json::to_value(value).map_err(|error| DocumentError::serde(key, error))?;
+        if !self.index.accepts(&v) {
+            return Err(DocumentError { key: key.to_string(), kind: DocumentErrorKind::Rejected });
+        }
+        self.index.insert(key, v);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.index.remove(key)
+    }
+
+    /// Returns the document stored under `key`
+    pub fn get(&self, key: &str) -> Option<Result<T, DocumentError>> {
+        let v = self.index.get(key)?;
+        Some(T::deserialize(v).map_err(|error| DocumentError::serde(key, error)))
+    }
+
+    /// Like `Index::find_where`, with one result per matched document
+    pub fn find_where<V>(&self, field: &str, op: Op, value: V) -> Vec<Result<(String, T), DocumentError>> where V: Serialize + for<'de> Deserialize<'de> {
+        self.index.find_where(field, op, value).get_as()
+    }
+
+    /// Like `Index::find`, with one result per matched document
+    pub fn find(&self, q: &Query) -> Vec<Result<(String, T), DocumentError>> {
+        self.index.find(q).get_as()
+    }
+
+    pub fn size(&self) -> usize {
+        self.index.size()
+    }
+}
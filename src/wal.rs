@@ -0,0 +1,185 @@
+//! Append-only write-ahead log of the changes made to an [`Index`](crate::Index).
+//!
+//! Every `insert`, `remove` and committed `Batch` is appended as one record before it is applied, so an index can be
+//! rebuilt after a restart by loading its last snapshot and replaying the log on top of it. A crash can leave a
+//! partially written record at the end of the log, replay stops at the first record that is cut short or does not
+//! match its checksum and the log is truncated there before new records are appended.
+//!
+//! ```text
+//! magic    4 bytes  "JWAL"
+//! version  u8       1
+//! records  [u32 LE length][u32 LE crc32][CBOR entry]
+//! ```
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::snapshot::{RecordReader, RecordWriter};
+use crate::{PersistError, RecoveryReport};
+
+const MAGIC: &[u8; 4] = b"JWAL";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+
+/// When appended log records are forced to disk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    /// fsync after every record, a change is durable once `try_insert`, `try_remove` or `Batch::try_commit` returns `Ok`
+    Always,
+    /// fsync after every n records
+    EveryN(usize),
+    /// never fsync, leave flushing to the operating system or call `Index::sync_wal`
+    Never,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum WalEntry {
+    Insert { key: String, value: Value },
+    Remove { key: String },
+    Batch { inserts: Vec<(String, Value)>, updates: Vec<(String, Value)>, deletes: Vec<String> },
+}
+
+pub(crate) struct Wal {
+    path: PathBuf,
+    records: RecordWriter<BufWriter<File>>,
+    policy: FsyncPolicy,
+    unsynced: usize,
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl Wal {
+    /// Opens the log at `path` for appending, creating it when missing. `end` is the length of its valid part as
+    /// returned by [`replay`], anything after it is cut off
+    pub(crate) fn open(path: &Path, policy: FsyncPolicy, end: u64) -> Result<Self, PersistError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let len = file.metadata()?.len();
+        if len < HEADER_LEN || end < HEADER_LEN {
+            file.set_len(0)?;
+            write_header(&mut file)?;
+            file.sync_all()?;
+        } else {
+            let mut header = [0u8; 5];
+            file.read_exact(&mut header)?;
+            if header[4] != VERSION {
+                return Err(PersistError::UnsupportedVersion(header[4]));
+            }
+            if len > end {
+                file.set_len(end)?;
+                file.sync_all()?;
+            }
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(Wal {
+            path: path.to_path_buf(),
+            records: RecordWriter::new(BufWriter::new(file)),
+            policy,
+            unsynced: 0,
+            error: None,
+        })
+    }
+
+    /// Appends an entry. a failed append can leave part of a record behind, so its error is kept and returned by `sync`
+    /// and every later append, the log never holds a change after a missing one
+    pub(crate) fn append(&mut self, entry: &WalEntry) -> io::Result<()> {
+        self.failed()?;
+        let result = self.records.write(entry).and_then(|_| {
+            self.unsynced += 1;
+            match self.policy {
+                FsyncPolicy::Always => self.sync_data(),
+                FsyncPolicy::EveryN(n) if self.unsynced >= n => self.sync_data(),
+                _ => Ok(())
+            }
+        });
+        if let Err(e) = &result {
+            self.error = Some((e.kind(), e.to_string()));
+        }
+        result
+    }
+
+    /// Flushes and fsyncs the appended records, returns the error of a failed append if any
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.failed()?;
+        self.sync_data()
+    }
+
+    fn failed(&self) -> io::Result<()> {
+        match &self.error {
+            Some((kind, msg)) => Err(io::Error::new(*kind, msg.to_string())),
+            None => Ok(())
+        }
+    }
+
+    /// Empties the log, called once its changes are in a snapshot
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        self.sync()?;
+        let file = self.records.get_mut().get_mut();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write_header(file)?;
+        file.sync_all()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        let w = self.records.get_mut();
+        w.flush()?;
+        w.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Calls `f` with every valid entry of the log at `path` in order. returns what was replayed and dropped along with
+/// the length of the valid part of the log. a missing log has no entries
+pub(crate) fn replay(path: &Path, f: &mut dyn FnMut(WalEntry)) -> Result<(RecoveryReport, u64), PersistError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((RecoveryReport::default(), 0)),
+        Err(e) => return Err(e.into())
+    };
+    let mut r = BufReader::new(file);
+    let mut header = [0u8; 5];
+    match r.read_exact(&mut header) {
+        Ok(_) => {}
+        // a crash while the log was created
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok((RecoveryReport::default(), 0)),
+        Err(e) => return Err(e.into())
+    }
+    if &header[..4] != MAGIC {
+        return Err(PersistError::NotAnIndex);
+    }
+    if header[4] != VERSION {
+        return Err(PersistError::UnsupportedVersion(header[4]));
+    }
+    let mut records = RecordReader::new(r);
+    let mut report = RecoveryReport::default();
+    loop {
+        match records.read_until_eof::<WalEntry>() {
+            Ok(Some(entry)) => f(entry),
+            Ok(None) => break,
+            Err(PersistError::Truncated { .. }) => {
+                report.dropped = 1;
+                break;
+            }
+            Err(e) if e.is_damage() => {
+                report.dropped = 1 + records.skip_remaining();
+                break;
+            }
+            Err(e) => return Err(e)
+        }
+    }
+    report.recovered = records.records();
+    Ok((report, HEADER_LEN + records.consumed()))
+}
+
+fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])
+}